@@ -0,0 +1,111 @@
+//! Makespan of a batch with one large file listed last, in list order and
+//! with `--sort-by-size desc`.
+//!
+//! A local server streams every file at the same per-connection rate, so a
+//! large file scheduled last keeps one worker busy long after the others
+//! have finished. Run with `cargo bench --bench sort_by_size`.
+
+use std::time::{Duration, Instant};
+
+use multifiledownloader::{cli::SizeOrder, client::ClientOptions, Downloader};
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream},
+};
+
+/// Bytes per second each connection is served at
+const RATE: usize = 100_000;
+/// Bytes written between pauses
+const CHUNK: usize = 10_000;
+const WORKERS: usize = 4;
+const SMALL_FILES: usize = 12;
+const SMALL_SIZE: usize = 50_000;
+const LARGE_SIZE: usize = 400_000;
+
+/// Serve `/<size>` as `size` zero bytes, throttled to [`RATE`]
+async fn serve(mut stream: TcpStream) -> std::io::Result<()> {
+  let mut request = Vec::new();
+  let mut buf = [0; 1024];
+  while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+    let n = stream.read(&mut buf).await?;
+    if n == 0 {
+      return Ok(());
+    }
+    request.extend_from_slice(&buf[..n]);
+  }
+  let request = String::from_utf8_lossy(&request);
+  let mut line = request.lines().next().unwrap_or_default().split(' ');
+  let method = line.next().unwrap_or_default();
+  let size: usize = line
+    .next()
+    .and_then(|path| path.trim_start_matches('/').parse().ok())
+    .unwrap_or(0);
+  stream
+    .write_all(
+      format!(
+        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+        size
+      )
+      .as_bytes(),
+    )
+    .await?;
+  if method == "GET" {
+    let chunk = vec![0; CHUNK];
+    let pause = Duration::from_secs_f64(CHUNK as f64 / RATE as f64);
+    for start in (0..size).step_by(CHUNK) {
+      stream.write_all(&chunk[..CHUNK.min(size - start)]).await?;
+      tokio::time::sleep(pause).await;
+    }
+  }
+  stream.shutdown().await
+}
+
+/// Time to download `urls` with `order`
+async fn makespan(urls: &[String], order: Option<SizeOrder>) -> Duration {
+  let dest = tempfile::tempdir().unwrap();
+  let downloader = Downloader::new(
+    urls.to_vec(),
+    dest.path().to_string_lossy().to_string(),
+    WORKERS,
+    false,
+    ClientOptions::default(),
+  )
+  .unwrap()
+  .with_sort_by_size(order)
+  .with_progress(false)
+  .build()
+  .unwrap();
+  let start = Instant::now();
+  let summary = downloader.run().await.unwrap();
+  assert_eq!(summary.succeeded, urls.len());
+  start.elapsed()
+}
+
+#[tokio::main]
+async fn main() {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  tokio::spawn(async move {
+    while let Ok((stream, _)) = listener.accept().await {
+      tokio::spawn(serve(stream));
+    }
+  });
+
+  // Distinct paths so every URL maps to its own file
+  let mut urls = (0..SMALL_FILES)
+    .map(|i| format!("http://{}/{}", addr, SMALL_SIZE + i))
+    .collect::<Vec<_>>();
+  urls.push(format!("http://{}/{}", addr, LARGE_SIZE));
+
+  let list_order = makespan(&urls, None).await;
+  let largest_first = makespan(&urls, Some(SizeOrder::Desc)).await;
+  println!(
+    "{} files of {} kB and one of {} kB on {} workers",
+    SMALL_FILES,
+    SMALL_SIZE / 1000,
+    LARGE_SIZE / 1000,
+    WORKERS
+  );
+  println!("list order:            {:>8.2?}", list_order);
+  println!("--sort-by-size desc:   {:>8.2?}", largest_first);
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use tokio::{
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+/// Per-host circuit breaker.
+///
+/// After `threshold` consecutive failures to a host the circuit opens and
+/// every request to that host is short-circuited. Once `cooldown` has passed
+/// a single trial request is let through (half-open): success closes the
+/// circuit again, failure re-opens it for another cooldown and any other
+/// outcome lets the next request try again.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+  threshold: u32,
+  cooldown:  Duration,
+  hosts:     Mutex<HashMap<String, HostState>>,
+}
+
+#[derive(Debug, Default)]
+struct HostState {
+  consecutive_failures: u32,
+  opened_at:            Option<Instant>,
+  trial_in_flight:      bool,
+}
+
+impl CircuitBreaker {
+  /// Create a breaker opening after `threshold` consecutive failures
+  pub fn new(threshold: u32, cooldown: Duration) -> Self {
+    Self {
+      threshold: threshold.max(1),
+      cooldown,
+      hosts: Default::default(),
+    }
+  }
+
+  /// Whether a request to `host` may be attempted
+  pub async fn allow(&self, host: &str) -> bool {
+    let mut hosts = self.hosts.lock().await;
+    let state = hosts.entry(host.to_string()).or_default();
+    let Some(opened_at) = state.opened_at else {
+      return true;
+    };
+    if opened_at.elapsed() < self.cooldown || state.trial_in_flight {
+      return false;
+    }
+    state.trial_in_flight = true;
+    true
+  }
+
+  /// Record a successful request to `host`, closing its circuit
+  pub async fn record_success(&self, host: &str) {
+    self.hosts.lock().await.remove(host);
+  }
+
+  /// Record a request to `host` that failed for reasons unrelated to the
+  /// host's health, e.g. a 404, releasing a half-open trial without closing
+  /// or re-opening the circuit
+  pub async fn record_neutral(&self, host: &str) {
+    if let Some(state) = self.hosts.lock().await.get_mut(host) {
+      state.trial_in_flight = false;
+    }
+  }
+
+  /// Record a failed request to `host`, opening its circuit once the
+  /// threshold is reached
+  pub async fn record_failure(&self, host: &str) {
+    let mut hosts = self.hosts.lock().await;
+    let state = hosts.entry(host.to_string()).or_default();
+    state.consecutive_failures += 1;
+    state.trial_in_flight = false;
+    if state.consecutive_failures >= self.threshold {
+      if state.opened_at.is_none() {
+        tracing::warn!(
+          "Host {} failed {} times in a row, skipping it for {}",
+          host,
+          state.consecutive_failures,
+          humantime::format_duration(self.cooldown)
+        );
+      }
+      state.opened_at = Some(Instant::now());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn opens_after_threshold_failures() {
+    let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+    breaker.record_failure("a").await;
+    assert!(breaker.allow("a").await);
+    breaker.record_failure("a").await;
+    assert!(!breaker.allow("a").await);
+    assert!(breaker.allow("b").await);
+  }
+
+  #[tokio::test]
+  async fn half_open_allows_a_single_trial() {
+    let breaker = CircuitBreaker::new(1, Duration::ZERO);
+    breaker.record_failure("a").await;
+    assert!(breaker.allow("a").await);
+    assert!(!breaker.allow("a").await);
+    breaker.record_success("a").await;
+    assert!(breaker.allow("a").await);
+    assert!(breaker.allow("a").await);
+  }
+
+  #[tokio::test]
+  async fn failed_trial_reopens_the_circuit() {
+    let breaker = CircuitBreaker::new(1, Duration::from_millis(50));
+    breaker.record_failure("a").await;
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert!(breaker.allow("a").await);
+    breaker.record_failure("a").await;
+    assert!(!breaker.allow("a").await);
+  }
+
+  #[tokio::test]
+  async fn neutral_trial_outcome_releases_the_trial() {
+    let breaker = CircuitBreaker::new(1, Duration::ZERO);
+    breaker.record_failure("a").await;
+    assert!(breaker.allow("a").await);
+    // e.g. the trial download got a 404
+    breaker.record_neutral("a").await;
+    assert!(breaker.allow("a").await);
+  }
+}
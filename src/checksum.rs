@@ -0,0 +1,243 @@
+use std::{
+  collections::HashMap,
+  fs::File,
+  io,
+  path::{Path, PathBuf},
+};
+
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+/// Hash algorithm of a [`Checksum`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+  Md5,
+  Sha1,
+  Sha256,
+  Sha512,
+}
+
+impl Algorithm {
+  /// Algorithm producing hex digests of `len` characters
+  fn from_hex_len(len: usize) -> Option<Self> {
+    match len {
+      32 => Some(Algorithm::Md5),
+      40 => Some(Algorithm::Sha1),
+      64 => Some(Algorithm::Sha256),
+      128 => Some(Algorithm::Sha512),
+      _ => None,
+    }
+  }
+}
+
+impl std::str::FromStr for Algorithm {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().replace('-', "").as_str() {
+      "md5" => Ok(Algorithm::Md5),
+      "sha1" => Ok(Algorithm::Sha1),
+      "sha256" => Ok(Algorithm::Sha256),
+      "sha512" => Ok(Algorithm::Sha512),
+      _ => Err(format!("Unknown checksum algorithm: {}", s)),
+    }
+  }
+}
+
+/// Expected digest of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+  pub algorithm: Algorithm,
+  /// Lowercase hex encoded digest
+  pub digest:    String,
+}
+
+impl std::str::FromStr for Checksum {
+  type Err = String;
+
+  /// Parse `<algorithm>:<hex>`, e.g. `sha256:9f86…`, or a bare hex digest
+  /// whose algorithm is inferred from its length
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.trim();
+    let (algorithm, digest) = match s.split_once(':') {
+      Some((algorithm, digest)) => (Some(algorithm.parse()?), digest),
+      None => (None, s),
+    };
+    if digest.is_empty() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+      return Err(format!("Invalid hex digest: {}", digest));
+    }
+    let algorithm = match algorithm {
+      Some(algorithm) => algorithm,
+      None => Algorithm::from_hex_len(digest.len())
+        .ok_or_else(|| format!("Cannot infer algorithm of digest: {}", s))?,
+    };
+    Ok(Checksum {
+      algorithm,
+      digest: digest.to_lowercase(),
+    })
+  }
+}
+
+/// Incremental digest, fed with the bytes of a download as they are written
+/// so verifying it does not read the file back
+pub enum Hasher {
+  Md5(Md5),
+  Sha1(Sha1),
+  Sha256(Sha256),
+  Sha512(Sha512),
+}
+
+impl Hasher {
+  pub fn new(algorithm: Algorithm) -> Self {
+    match algorithm {
+      Algorithm::Md5 => Hasher::Md5(Md5::new()),
+      Algorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+      Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+      Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+    }
+  }
+
+  pub fn update(&mut self, bytes: &[u8]) {
+    match self {
+      Hasher::Md5(hasher) => Digest::update(hasher, bytes),
+      Hasher::Sha1(hasher) => Digest::update(hasher, bytes),
+      Hasher::Sha256(hasher) => Digest::update(hasher, bytes),
+      Hasher::Sha512(hasher) => Digest::update(hasher, bytes),
+    }
+  }
+
+  /// Feed the contents of the file at `path`, e.g. the partial a resumed
+  /// download continues
+  pub fn update_file(&mut self, path: &Path) -> io::Result<()> {
+    io::copy(&mut File::open(path)?, self)?;
+    Ok(())
+  }
+
+  /// Hex encoded digest of everything fed so far
+  pub fn finalize(self) -> String {
+    let digest = match self {
+      Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+      Hasher::Sha1(hasher) => hasher.finalize().to_vec(),
+      Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+      Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+    };
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+}
+
+impl io::Write for Hasher {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.update(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Compute the hex encoded `algorithm` digest of the file at `path`
+pub fn digest_file(path: &Path, algorithm: Algorithm) -> io::Result<String> {
+  let mut hasher = Hasher::new(algorithm);
+  hasher.update_file(path)?;
+  Ok(hasher.finalize())
+}
+
+/// Compute the hex encoded SHA-256 digest of the file at `path`
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+  digest_file(path, Algorithm::Sha256)
+}
+
+/// Find the SHA-256 digest for `filename` in the contents of a checksum file.
+///
+/// Accepts a bare digest as well as `sha256sum` output (`<digest>  <name>`
+/// per line), where the line naming `filename` wins over the first one.
+pub fn parse_checksum_file(text: &str, filename: &str) -> Option<String> {
+  let entries = text
+    .lines()
+    .filter_map(|line| {
+      let mut parts = line.split_whitespace();
+      let digest = parts.next()?;
+      let name = parts.next().map(|n| n.trim_start_matches('*'));
+      Some((digest, name))
+    })
+    .filter(|(digest, _)| {
+      digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit())
+    })
+    .collect::<Vec<_>>();
+  entries
+    .iter()
+    .find(|(_, name)| *name == Some(filename))
+    .or(entries.first())
+    .map(|(digest, _)| digest.to_lowercase())
+}
+
+/// Parse `sha256sum` style lines (`<digest>  <name-or-url>`) into a map of
+/// filename or URL to lowercase SHA-256 digest.
+///
+/// Blank lines, `#` comments and lines without a valid digest are skipped.
+pub fn parse_checksum_map(text: &str) -> HashMap<String, String> {
+  text
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    .filter_map(|line| {
+      let (digest, name) = line.split_once(char::is_whitespace)?;
+      let name = name.trim().trim_start_matches('*');
+      (digest.len() == 64
+        && digest.chars().all(|c| c.is_ascii_hexdigit())
+        && !name.is_empty())
+      .then(|| (name.to_string(), digest.to_lowercase()))
+    })
+    .collect()
+}
+
+/// Group `paths` by content and return `(original, duplicate)` pairs, where
+/// `original` is the first path (in input order) with identical content.
+///
+/// Files that cannot be read are skipped.
+pub fn find_duplicates(paths: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+  let mut seen: HashMap<String, PathBuf> = HashMap::new();
+  let mut duplicates = Vec::new();
+  for path in paths {
+    let Ok(digest) = sha256_file(path) else {
+      continue;
+    };
+    match seen.get(&digest) {
+      Some(original) => duplicates.push((original.clone(), path.clone())),
+      None => {
+        seen.insert(digest, path.clone());
+      },
+    }
+  }
+  duplicates
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use super::*;
+
+  #[test]
+  fn incremental_digest_matches_file_digest() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(b"hello world").unwrap();
+    for algorithm in
+      [Algorithm::Md5, Algorithm::Sha1, Algorithm::Sha256, Algorithm::Sha512]
+    {
+      let mut hasher = Hasher::new(algorithm);
+      hasher.update(b"hello");
+      hasher.update(b" world");
+      assert_eq!(
+        hasher.finalize(),
+        digest_file(file.path(), algorithm).unwrap()
+      );
+    }
+    assert_eq!(
+      sha256_file(file.path()).unwrap(),
+      "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+    );
+  }
+}
@@ -0,0 +1,304 @@
+use std::{path::PathBuf, sync::Arc};
+
+use reqwest::{
+  dns::{Addrs, Name, Resolve, Resolving},
+  header::{HeaderMap, HeaderName, HeaderValue},
+  Certificate,
+  Client,
+  Identity,
+  RequestBuilder,
+};
+
+use crate::{
+  doh::DohResolver,
+  error::{DownloadError, Result},
+};
+
+/// Options used to build the reqwest [`Client`] shared by all requests
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+  /// Only speak HTTP/1.1, even if the server offers HTTP/2
+  pub http1_only: bool,
+  /// Accept certificates whose hostname does not match the server
+  pub accept_invalid_hostnames: bool,
+  /// Accept any certificate, skipping TLS verification entirely
+  pub accept_invalid_certs: bool,
+  /// TCP keepalive interval, reqwest's default when unset
+  pub tcp_keepalive: Option<std::time::Duration>,
+  /// Whether to set `TCP_NODELAY`, reqwest's default when unset
+  pub tcp_nodelay: Option<bool>,
+  /// Timeout of each request, body included
+  pub timeout: Option<std::time::Duration>,
+  /// Timeout for establishing connections
+  pub connect_timeout: Option<std::time::Duration>,
+  /// `User-Agent` sent with every request, reqwest's default when unset
+  pub user_agent: Option<String>,
+  /// Headers sent with every request, as given on the command line
+  pub headers: Vec<(String, String)>,
+  /// Expand `${VAR}` references in header values from the environment
+  pub expand_header_env: bool,
+  /// DNS-over-HTTPS endpoint used instead of the system resolver
+  pub doh: Option<url::Url>,
+  /// Connect over IPv4 first, racing IPv6 only as the fallback
+  pub prefer_ipv4: bool,
+  /// Largest HTTP/2 header list accepted from servers
+  pub max_header_bytes: Option<u64>,
+  /// Client certificate for mutual TLS
+  pub identity: Option<ClientIdentity>,
+  /// PEM CA certificates trusted in addition to the system roots
+  pub ca_certs: Vec<PathBuf>,
+  /// Return redirect responses instead of following them
+  pub no_redirects: bool,
+  /// Proxy for all requests but those to `NO_PROXY` hosts, replacing
+  /// `HTTP_PROXY`/`HTTPS_PROXY` and tried before PAC
+  pub proxy: Option<String>,
+  /// Proxy auto-configuration script selecting the proxy per URL
+  #[cfg(feature = "pac")]
+  pub pac_script: Option<String>,
+}
+
+impl ClientOptions {
+  /// Build a [`Client`] from these options
+  pub fn build(&self) -> Result<Client> {
+    let mut builder = Client::builder();
+    if self.http1_only {
+      builder = builder.http1_only();
+    }
+    if self.accept_invalid_hostnames {
+      builder = builder.danger_accept_invalid_hostnames(true);
+    }
+    if self.accept_invalid_certs {
+      builder = builder.danger_accept_invalid_certs(true);
+    }
+    if self.no_redirects {
+      builder = builder.redirect(reqwest::redirect::Policy::none());
+    }
+    if let Some(interval) = self.tcp_keepalive {
+      builder = builder.tcp_keepalive(interval);
+    }
+    if let Some(nodelay) = self.tcp_nodelay {
+      builder = builder.tcp_nodelay(nodelay);
+    }
+    if let Some(timeout) = self.timeout {
+      builder = builder.timeout(timeout);
+    }
+    if let Some(timeout) = self.connect_timeout {
+      builder = builder.connect_timeout(timeout);
+    }
+    if let Some(identity) = &self.identity {
+      builder = builder.identity(identity.load()?);
+    }
+    for path in &self.ca_certs {
+      builder = builder.add_root_certificate(load_ca_cert(path)?);
+    }
+    if let Some(user_agent) = &self.user_agent {
+      builder = builder.user_agent(user_agent);
+    }
+    if let Some(max) = self.max_header_bytes {
+      let max = u32::try_from(max).unwrap_or(u32::MAX);
+      builder = builder.http2_max_header_list_size(max);
+    }
+    if !self.headers.is_empty() {
+      builder = builder.default_headers(self.header_map()?);
+    }
+    let doh = match &self.doh {
+      // Reach the endpoint with the same TLS and proxy settings, but without
+      // the headers meant for the download servers
+      Some(endpoint) => {
+        let client = ClientOptions {
+          doh: None,
+          headers: Vec::new(),
+          no_redirects: false,
+          ..self.clone()
+        }
+        .build()?;
+        Some(DohResolver::new(endpoint.clone(), client)?)
+      },
+      None => None,
+    };
+    if self.prefer_ipv4 {
+      builder = builder.dns_resolver(Arc::new(PreferIpv4(doh)));
+    } else if let Some(doh) = doh {
+      builder = builder.dns_resolver(Arc::new(doh));
+    }
+    // Proxies are tried in the order they are added. Adding any turns off
+    // reqwest's own `HTTP(S)_PROXY` lookup, so the environment proxies only
+    // apply when neither `--proxy` nor PAC is configured.
+    if let Some(proxy) = &self.proxy {
+      let proxy = reqwest::Proxy::all(proxy)
+        .map_err(|e| DownloadError::ProxyError(e.to_string()))?
+        .no_proxy(reqwest::NoProxy::from_env());
+      builder = builder.proxy(proxy);
+    }
+    #[cfg(feature = "pac")]
+    if let Some(script) = &self.pac_script {
+      let resolver = Arc::new(crate::pac::PacResolver::new(script.clone())?);
+      builder = builder
+        .proxy(reqwest::Proxy::custom(move |url| resolver.find_proxy(url)));
+    }
+    Ok(builder.build()?)
+  }
+
+  /// Build the default headers, expanding environment variables in their
+  /// values if enabled.
+  ///
+  /// Values are marked sensitive so they are redacted from `Debug` output,
+  /// errors only name the offending header.
+  fn header_map(&self) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &self.headers {
+      let invalid = || DownloadError::InvalidHeader(name.clone());
+      let value = if self.expand_header_env {
+        crate::Downloader::shellexpand_full(value)
+      } else {
+        value.clone()
+      };
+      let name =
+        HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+      let mut value = HeaderValue::from_str(&value).map_err(|_| invalid())?;
+      value.set_sensitive(true);
+      headers.append(name, value);
+    }
+    Ok(headers)
+  }
+}
+
+/// Credentials sent with requests to the download URLs
+#[derive(Clone)]
+pub enum Auth {
+  /// `Authorization: Bearer <token>`
+  Bearer(String),
+  /// `Authorization: Basic ...`
+  Basic { user: String, password: Option<String> },
+}
+
+impl Auth {
+  /// Add these credentials to `request`
+  pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+    match self {
+      Auth::Bearer(token) => request.bearer_auth(token),
+      Auth::Basic {
+        user,
+        password,
+      } => request.basic_auth(user, password.as_ref()),
+    }
+  }
+}
+
+/// Masks the credentials, `Downloader` is logged with its `Debug` impl
+impl std::fmt::Debug for Auth {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Auth::Bearer(_) => f.write_str("Bearer(<redacted>)"),
+      Auth::Basic {
+        user,
+        ..
+      } => {
+        write!(f, "Basic {{ user: {:?}, password: <redacted> }}", user)
+      },
+    }
+  }
+}
+
+/// Client certificate presented for mutual TLS
+#[derive(Clone)]
+pub enum ClientIdentity {
+  /// PEM certificate (chain) and PKCS#8 PEM private key files
+  Pem { cert: PathBuf, key: PathBuf },
+  /// PKCS#12 archive holding both, and its password
+  Pkcs12 { path: PathBuf, password: String },
+}
+
+impl ClientIdentity {
+  /// Load the identity, failing if the files cannot be read or parsed or
+  /// the key does not match the certificate
+  fn load(&self) -> Result<Identity> {
+    let read = |path: &PathBuf| {
+      std::fs::read(path).map_err(|e| {
+        DownloadError::ClientCertError(format!("{}: {}", path.display(), e))
+      })
+    };
+    let (identity, files) = match self {
+      ClientIdentity::Pem {
+        cert,
+        key,
+      } => (
+        Identity::from_pkcs8_pem(&read(cert)?, &read(key)?),
+        format!("{} and {}", cert.display(), key.display()),
+      ),
+      ClientIdentity::Pkcs12 {
+        path,
+        password,
+      } => (
+        Identity::from_pkcs12_der(&read(path)?, password),
+        path.display().to_string(),
+      ),
+    };
+    let invalid = |e: reqwest::Error| {
+      let cause = std::error::Error::source(&e)
+        .map(ToString::to_string)
+        .unwrap_or_else(|| e.to_string());
+      DownloadError::ClientCertError(format!("{}: {}", files, cause))
+    };
+    let identity = identity.map_err(invalid)?;
+    // The key is only matched against the certificate when building a client
+    Client::builder().identity(identity.clone()).build().map_err(invalid)?;
+    Ok(identity)
+  }
+}
+
+/// Read the PEM CA certificate at `path`
+fn load_ca_cert(path: &std::path::Path) -> Result<Certificate> {
+  let invalid = |cause: String| {
+    DownloadError::CaCertError(format!("{}: {}", path.display(), cause))
+  };
+  let pem = std::fs::read(path).map_err(|e| invalid(e.to_string()))?;
+  Certificate::from_pem(&pem).map_err(|e| {
+    invalid(
+      std::error::Error::source(&e)
+        .map(ToString::to_string)
+        .unwrap_or_else(|| e.to_string()),
+    )
+  })
+}
+
+/// Masks the password, `Downloader` is logged with its `Debug` impl
+impl std::fmt::Debug for ClientIdentity {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ClientIdentity::Pem {
+        cert,
+        key,
+      } => write!(f, "Pem {{ cert: {:?}, key: {:?} }}", cert, key),
+      ClientIdentity::Pkcs12 {
+        path,
+        ..
+      } => {
+        write!(f, "Pkcs12 {{ path: {:?}, password: <redacted> }}", path)
+      },
+    }
+  }
+}
+
+/// Resolver listing IPv4 addresses first.
+///
+/// The connector tries the family of the first address and races the other
+/// family as its happy-eyeballs fallback, so this avoids stalling on
+/// advertised but broken IPv6.
+struct PreferIpv4(Option<DohResolver>);
+
+impl Resolve for PreferIpv4 {
+  fn resolve(&self, name: Name) -> Resolving {
+    let doh = self.0.clone();
+    Box::pin(async move {
+      let addrs = match doh {
+        Some(doh) => doh.resolve(name).await?.collect::<Vec<_>>(),
+        None => tokio::net::lookup_host((name.as_str(), 0)).await?.collect(),
+      };
+      let (mut v4, v6): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|addr| addr.is_ipv4());
+      v4.extend(v6);
+      Ok(Box::new(v4.into_iter()) as Addrs)
+    })
+  }
+}
@@ -0,0 +1,169 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use reqwest::Client;
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// Options for `--recursive` crawling
+#[derive(Debug, Clone)]
+pub struct CrawlOptions {
+  /// How many links deep to follow from the seed URLs
+  pub max_depth:      usize,
+  /// Only follow links on the host of the URL they were found on
+  pub same_host:      bool,
+  /// Skip URLs disallowed for all user agents by the host's robots.txt
+  pub respect_robots: bool,
+  /// Stop after visiting this many URLs
+  pub max_urls:       usize,
+}
+
+impl Default for CrawlOptions {
+  fn default() -> Self {
+    Self {
+      max_depth:      1,
+      same_host:      false,
+      respect_robots: false,
+      max_urls:       1000,
+    }
+  }
+}
+
+/// Crawl HTML pages starting at `seeds`, following links up to
+/// `opts.max_depth` deep, and return the URLs of the non-HTML files found.
+///
+/// Seeds that are not HTML pages are returned as-is.
+pub async fn crawl(
+  client: &Client,
+  seeds: Vec<String>,
+  opts: &CrawlOptions,
+) -> Vec<String> {
+  let mut visited = HashSet::new();
+  let mut robots: HashMap<String, Vec<String>> = HashMap::new();
+  let mut queue = seeds
+    .into_iter()
+    .filter_map(|s| Url::parse(&s).ok())
+    .map(|u| (u, 0))
+    .collect::<VecDeque<_>>();
+  let mut files = Vec::new();
+
+  while let Some((url, depth)) = queue.pop_front() {
+    if visited.len() >= opts.max_urls {
+      warn!("Reached --max-urls {}, stopping crawl", opts.max_urls);
+      break;
+    }
+    if !visited.insert(url.to_string()) {
+      continue;
+    }
+    if opts.respect_robots {
+      let origin = url.origin().ascii_serialization();
+      if !robots.contains_key(&origin) {
+        let rules = fetch_robots(client, &url).await;
+        robots.insert(origin.clone(), rules);
+      }
+      if robots[&origin].iter().any(|p| url.path().starts_with(p.as_str())) {
+        debug!("Disallowed by robots.txt: {}", url);
+        continue;
+      }
+    }
+
+    let is_html = match client.head(url.as_str()).send().await {
+      Ok(resp) => resp
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html")),
+      Err(e) => {
+        warn!("Failed to crawl {}: {}", url, e);
+        continue;
+      },
+    };
+    if !is_html {
+      files.push(url.to_string());
+      continue;
+    }
+    if depth >= opts.max_depth {
+      continue;
+    }
+    let body = match client.get(url.as_str()).send().await {
+      Ok(resp) => resp.text().await.unwrap_or_default(),
+      Err(e) => {
+        warn!("Failed to crawl {}: {}", url, e);
+        continue;
+      },
+    };
+    for link in extract_links(&body) {
+      let Ok(mut link) = url.join(&link) else {
+        continue;
+      };
+      link.set_fragment(None);
+      if !matches!(link.scheme(), "http" | "https")
+        || (opts.same_host && link.host_str() != url.host_str())
+      {
+        continue;
+      }
+      if !visited.contains(link.as_str()) {
+        queue.push_back((link, depth + 1));
+      }
+    }
+  }
+  info!("Crawled {} URLs and found {} files", visited.len(), files.len());
+  files
+}
+
+/// Extract the values of all `href` attributes in `html`
+pub fn extract_links(html: &str) -> Vec<String> {
+  let lower = html.to_ascii_lowercase();
+  let mut links = Vec::new();
+  let mut pos = 0;
+  while let Some(found) = lower[pos..].find("href") {
+    let mut i = pos + found + 4;
+    pos = i;
+    let rest = &html[i..];
+    let trimmed = rest.trim_start();
+    if !trimmed.starts_with('=') {
+      continue;
+    }
+    i += rest.len() - trimmed.len() + 1;
+    let value = html[i..].trim_start();
+    let link = match value.chars().next() {
+      Some(quote @ ('"' | '\'')) => value[1..].split(quote).next(),
+      Some(_) => value.split(|c: char| c.is_whitespace() || c == '>').next(),
+      None => None,
+    };
+    if let Some(link) = link.map(str::trim).filter(|l| !l.is_empty()) {
+      links.push(link.to_string());
+    }
+  }
+  links
+}
+
+/// Fetch the `Disallow` path prefixes for all user agents (`*`) from the
+/// robots.txt of `url`'s host, empty if it is missing
+async fn fetch_robots(client: &Client, url: &Url) -> Vec<String> {
+  let Ok(robots_url) = url.join("/robots.txt") else {
+    return Vec::new();
+  };
+  let body = match client.get(robots_url).send().await {
+    Ok(resp) if resp.status().is_success() => {
+      resp.text().await.unwrap_or_default()
+    },
+    _ => return Vec::new(),
+  };
+  let mut disallowed = Vec::new();
+  let mut applies = false;
+  for line in body.lines() {
+    let line = line.split('#').next().unwrap_or_default().trim();
+    let Some((key, value)) = line.split_once(':') else {
+      continue;
+    };
+    let value = value.trim();
+    match key.trim().to_ascii_lowercase().as_str() {
+      "user-agent" => applies = value == "*",
+      "disallow" if applies && !value.is_empty() => {
+        disallowed.push(value.to_string())
+      },
+      _ => (),
+    }
+  }
+  disallowed
+}
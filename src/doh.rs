@@ -0,0 +1,129 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use reqwest::{
+  dns::{Addrs, Name, Resolve, Resolving},
+  Client,
+};
+use serde::Deserialize;
+use url::Url;
+
+use crate::error::{DownloadError, Result};
+
+/// DNS record types requested from the DoH endpoint
+const RECORD_TYPES: [(&str, u16); 2] = [("A", 1), ("AAAA", 28)];
+
+/// Resolves host names through a DNS-over-HTTPS endpoint speaking the JSON
+/// API (`application/dns-json`, e.g. `https://cloudflare-dns.com/dns-query`
+/// or `https://dns.google/resolve`).
+///
+/// Falls back to the system resolver with a warning when the endpoint fails.
+#[derive(Debug, Clone)]
+pub struct DohResolver {
+  endpoint: Arc<Url>,
+  /// Client used for DoH queries, it resolves the endpoint itself with the
+  /// system resolver
+  client:   Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct DohResponse {
+  #[serde(rename = "Status")]
+  status: u32,
+  #[serde(rename = "Answer", default)]
+  answer: Vec<DohAnswer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DohAnswer {
+  #[serde(rename = "type")]
+  kind: u16,
+  data: String,
+}
+
+impl DohResolver {
+  /// Create a resolver querying `endpoint`, which must be an https URL, with
+  /// `client`
+  pub fn new(endpoint: Url, client: Client) -> Result<Self> {
+    if endpoint.scheme() != "https" || !endpoint.has_host() {
+      return Err(DownloadError::InvalidUrlError(format!(
+        "DoH endpoint must be an https URL: {}",
+        endpoint
+      )));
+    }
+    Ok(Self {
+      endpoint: Arc::new(endpoint),
+      client,
+    })
+  }
+
+  async fn query(
+    &self,
+    name: &str,
+  ) -> std::result::Result<Vec<SocketAddr>, reqwest::Error> {
+    let mut addrs = Vec::new();
+    for (record, kind) in RECORD_TYPES {
+      let resp = self
+        .client
+        .get(self.endpoint.as_str())
+        .query(&[("name", name), ("type", record)])
+        .header("accept", "application/dns-json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<DohResponse>()
+        .await?;
+      if resp.status != 0 {
+        continue;
+      }
+      addrs.extend(
+        resp
+          .answer
+          .iter()
+          .filter(|a| a.kind == kind)
+          .filter_map(|a| a.data.parse().ok())
+          .map(|ip| SocketAddr::new(ip, 0)),
+      );
+    }
+    Ok(addrs)
+  }
+}
+
+impl Resolve for DohResolver {
+  fn resolve(&self, name: Name) -> Resolving {
+    let resolver = self.clone();
+    Box::pin(async move {
+      let name = name.as_str();
+      match resolver.query(name).await {
+        Ok(addrs) if !addrs.is_empty() => {
+          return Ok(Box::new(addrs.into_iter()) as Addrs);
+        },
+        Ok(_) => tracing::warn!(
+          "DoH returned no addresses for {}, falling back to system DNS",
+          name
+        ),
+        Err(e) => tracing::warn!(
+          "DoH lookup of {} failed, falling back to system DNS: {}",
+          name,
+          e
+        ),
+      }
+      let addrs = tokio::net::lookup_host((name, 0)).await?;
+      Ok(Box::new(addrs.collect::<Vec<_>>().into_iter()) as Addrs)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn only_https_endpoints_are_accepted() {
+    let resolver = |endpoint: &str| {
+      DohResolver::new(Url::parse(endpoint).unwrap(), Client::new())
+    };
+    assert!(resolver("https://dns.google/resolve").is_ok());
+    assert!(resolver("http://dns.google/resolve").is_err());
+    assert!(resolver("ftp://dns.google/resolve").is_err());
+  }
+}
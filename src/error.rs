@@ -1,16 +1,24 @@
 use thiserror::Error;
 
+use crate::summary::RetryReason;
+
 #[derive(Error, Debug)]
 pub enum DownloadError {
   #[error("Failed to parse URL: {0}")]
   UrlParseError(#[from] url::ParseError),
 
   #[error("HTTP request failed: {0}")]
-  ReqwestError(#[from] reqwest::Error),
+  ReqwestError(reqwest::Error),
+
+  #[error("HTTP request timed out: {0}")]
+  Timeout(reqwest::Error),
 
   #[error("IO error: {0}")]
   IoError(#[from] std::io::Error),
 
+  #[error("Invalid configuration: {0}")]
+  ConfigError(String),
+
   #[error("Failed to create destination directory: {0}")]
   DestDirError(String),
 
@@ -20,8 +28,129 @@ pub enum DownloadError {
   #[error("Invalid URL format: {0}")]
   InvalidUrlError(String),
 
+  #[error("Presigned URL has expired, regenerate it before retrying: {0}")]
+  PresignedUrlExpired(String),
+
+  #[error("URL refresh command failed: {0}")]
+  RefreshCommandError(String),
+
+  #[error("Too many redirects: {0}")]
+  TooManyRedirects(String),
+
+  #[error("Proxy configuration error: {0}")]
+  ProxyError(String),
+
+  #[error(
+    "Incomplete download of {url}: expected {expected} bytes, got {actual}"
+  )]
+  IncompleteDownload { url: String, expected: u64, actual: u64 },
+
+  #[error("Downloads drifted from the lock file:\n{0}")]
+  LockMismatch(String),
+
+  #[error("Checksum mismatch for {url}: expected {expected}, got {actual}")]
+  ChecksumMismatch { url: String, expected: String, actual: String },
+
+  #[error(
+    "No progress for {}, aborted the run",
+    humantime::format_duration(*.0)
+  )]
+  IdleTimeout(std::time::Duration),
+
+  #[error("Server ignored the range request for a split download: {0}")]
+  RangeNotSupported(String),
+
+  #[error(
+    "Response headers of {url} are too large: {size} bytes, limit is {limit}"
+  )]
+  ResponseHeadersTooLarge { url: String, size: u64, limit: u64 },
+
+  #[error("Invalid client certificate: {0}")]
+  ClientCertError(String),
+
+  #[error("Invalid CA certificate: {0}")]
+  CaCertError(String),
+
+  #[error("Invalid header: {0}")]
+  InvalidHeader(String),
+
+  #[error("Skipped, host is down: {0}")]
+  HostUnavailable(String),
+
   #[error("Indicatif error: {0}")]
   IndicatifError(#[from] indicatif::style::TemplateError),
 }
 
+impl From<reqwest::Error> for DownloadError {
+  fn from(e: reqwest::Error) -> Self {
+    if e.is_timeout() {
+      DownloadError::Timeout(e)
+    } else {
+      DownloadError::ReqwestError(e)
+    }
+  }
+}
+
+impl DownloadError {
+  /// Whether this error indicates the remote host itself is failing
+  /// (connection problems, timeouts or server errors) rather than a problem
+  /// with the request or the local machine
+  pub fn is_host_failure(&self) -> bool {
+    match self {
+      DownloadError::ReqwestError(e) => {
+        e.is_connect()
+          || e.is_timeout()
+          || e.is_request()
+          || e.status().is_some_and(|s| s.is_server_error())
+      },
+      DownloadError::Timeout(_) => true,
+      _ => false,
+    }
+  }
+
+  /// Why the request that failed with this error is worth retrying, `None`
+  /// if it is not: connection failures, resets and timeouts, 5xx responses,
+  /// bodies cut short and checksum mismatches
+  pub fn retry_reason(&self) -> Option<RetryReason> {
+    match self {
+      DownloadError::Timeout(_) => Some(RetryReason::Timeout),
+      DownloadError::ReqwestError(e)
+        if e.status().is_some_and(|s| s.is_server_error()) =>
+      {
+        Some(RetryReason::ServerError)
+      },
+      DownloadError::ReqwestError(e)
+        if e.is_connect() || e.is_request() || e.is_body() =>
+      {
+        Some(RetryReason::Network)
+      },
+      DownloadError::IncompleteDownload {
+        expected,
+        actual,
+        ..
+      } if actual < expected => Some(RetryReason::Network),
+      DownloadError::ChecksumMismatch {
+        ..
+      } => Some(RetryReason::Checksum),
+      _ => None,
+    }
+  }
+
+  /// Whether this error was caused by the disk running out of space
+  pub fn is_disk_full(&self) -> bool {
+    matches!(self, DownloadError::IoError(e) if e.kind() == std::io::ErrorKind::StorageFull)
+  }
+
+  /// Process exit code for a run aborted by this error
+  pub fn exit_code(&self) -> i32 {
+    if self.is_disk_full() {
+      crate::summary::EXIT_DISK_FULL
+    } else if let DownloadError::IdleTimeout(_) = self {
+      crate::summary::EXIT_DEADLINE
+    } else {
+      crate::summary::EXIT_ERROR
+    }
+  }
+}
+
 pub type Result<T> = std::result::Result<T, DownloadError>;
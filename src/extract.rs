@@ -0,0 +1,124 @@
+use std::{
+  fs::{self, File},
+  io::{self, Read},
+  path::{Component, Path, PathBuf},
+};
+
+/// Archive formats `--extract` understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+  Zip,
+  Tar,
+  TarGz,
+}
+
+impl ArchiveKind {
+  /// Detect the archive kind of `path` from its extension, falling back to
+  /// its magic bytes
+  pub fn detect(path: &Path) -> Option<Self> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") {
+      return Some(Self::Zip);
+    }
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+      return Some(Self::TarGz);
+    }
+    if name.ends_with(".tar") {
+      return Some(Self::Tar);
+    }
+    let mut magic = [0u8; 4];
+    File::open(path).ok()?.read_exact(&mut magic).ok()?;
+    match magic {
+      [b'P', b'K', 3, 4] => Some(Self::Zip),
+      [0x1f, 0x8b, ..] => Some(Self::TarGz),
+      _ => None,
+    }
+  }
+}
+
+/// Number of archives extracted at once, half of the CPUs so extraction
+/// does not starve the downloads
+pub fn default_concurrency() -> usize {
+  std::thread::available_parallelism().map(|n| n.get().div_ceil(2)).unwrap_or(1)
+}
+
+/// Directory an archive is extracted into: next to it, named after it
+/// without the archive extension
+pub fn target_dir(archive: &Path) -> PathBuf {
+  let name = archive.file_name().unwrap_or_default().to_string_lossy();
+  let lower = name.to_lowercase();
+  let stem = [".tar.gz", ".tgz", ".tar", ".zip"]
+    .iter()
+    .find(|ext| lower.ends_with(*ext))
+    .map(|ext| &name[..name.len() - ext.len()])
+    .filter(|stem| !stem.is_empty())
+    .unwrap_or(&name);
+  let dir = archive.with_file_name(stem);
+  if dir == archive {
+    archive.with_file_name(format!("{}.extracted", name))
+  } else {
+    dir
+  }
+}
+
+/// Whether `path` stays inside the directory it is joined onto
+fn is_enclosed(path: &Path) -> bool {
+  path
+    .components()
+    .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Extract `archive` into [`target_dir`], refusing entries that would land
+/// outside of it (zip-slip)
+pub fn extract(archive: &Path, kind: ArchiveKind) -> io::Result<PathBuf> {
+  let dest = target_dir(archive);
+  fs::create_dir_all(&dest)?;
+  let file = File::open(archive)?;
+  match kind {
+    ArchiveKind::Zip => {
+      let mut zip = zip::ZipArchive::new(file).map_err(io::Error::other)?;
+      for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(io::Error::other)?;
+        let Some(name) = entry.enclosed_name().filter(|n| is_enclosed(n))
+        else {
+          return Err(unsafe_entry(entry.name()));
+        };
+        let path = dest.join(name);
+        if entry.is_dir() {
+          fs::create_dir_all(&path)?;
+          continue;
+        }
+        if let Some(parent) = path.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut entry, &mut File::create(&path)?)?;
+      }
+    },
+    ArchiveKind::Tar => unpack_tar(tar::Archive::new(file), &dest)?,
+    ArchiveKind::TarGz => {
+      unpack_tar(tar::Archive::new(flate2::read::GzDecoder::new(file)), &dest)?
+    },
+  }
+  Ok(dest)
+}
+
+fn unpack_tar<R: Read>(
+  mut archive: tar::Archive<R>,
+  dest: &Path,
+) -> io::Result<()> {
+  for entry in archive.entries()? {
+    let mut entry = entry?;
+    let path = entry.path()?.into_owned();
+    if !is_enclosed(&path) || !entry.unpack_in(dest)? {
+      return Err(unsafe_entry(&path.to_string_lossy()));
+    }
+  }
+  Ok(())
+}
+
+fn unsafe_entry(name: &str) -> io::Error {
+  io::Error::new(
+    io::ErrorKind::InvalidData,
+    format!("Archive entry escapes the target directory: {}", name),
+  )
+}
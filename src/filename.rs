@@ -0,0 +1,591 @@
+use std::path::{Component, Path, PathBuf};
+
+use unicode_normalization::UnicodeNormalization;
+use url::Url;
+
+use crate::{
+  cli::SuffixPlacement,
+  error::{DownloadError, Result},
+};
+
+/// Filename used when nothing usable can be derived from a URL
+pub const FALLBACK_FILENAME: &str = "downloaded_file";
+
+/// Longest filename, in bytes, accepted by common filesystems
+const MAX_FILENAME_BYTES: usize = 255;
+
+/// Options controlling how local filenames are derived from URLs
+#[derive(Debug, Clone, Default)]
+pub struct FilenameOptions {
+  /// Lowercase derived filenames
+  pub lowercase:        bool,
+  /// Percent-decode, NFC-normalize and trim derived filenames
+  pub normalize:        bool,
+  /// Keep the query string in derived filenames instead of stripping it
+  pub keep_query:       bool,
+  /// Keep the fragment in derived filenames instead of stripping it
+  pub keep_fragment:    bool,
+  /// Prepended to derived filenames
+  pub prefix:           Option<String>,
+  /// Appended to derived filenames, see `suffix_placement`
+  pub suffix:           Option<String>,
+  /// Where `suffix` goes
+  pub suffix_placement: SuffixPlacement,
+  /// Trim trailing dots and spaces from filenames given explicitly too,
+  /// always done on Windows which strips them
+  pub trim:             bool,
+  /// Keep the directories of the URL path, nesting the filename in them
+  pub preserve_path:    bool,
+}
+
+impl FilenameOptions {
+  /// Derive the local filename for `url` and apply the configured
+  /// normalizations to it
+  pub fn derive(&self, url: &str) -> String {
+    self
+      .nest(url, self.apply(from_url(url, self.keep_query, self.keep_fragment)))
+  }
+
+  /// Nest `filename` in the directories of the path of `url`, each made safe
+  /// like filenames, if `preserve_path` is set
+  pub fn nest(&self, url: &str, filename: String) -> String {
+    if !self.preserve_path {
+      return filename;
+    }
+    let mut path = dirs_of(url)
+      .into_iter()
+      .map(|dir| {
+        let dir = if self.normalize {
+          normalize(&dir)
+        } else {
+          sanitize(&dir)
+        };
+        if self.lowercase {
+          dir.to_lowercase()
+        } else {
+          dir
+        }
+      })
+      .collect::<Vec<_>>();
+    path.push(filename);
+    path.join("/")
+  }
+
+  /// Apply the configured normalizations to a derived `filename`
+  pub fn apply(&self, filename: String) -> String {
+    let mut filename = filename;
+    if self.normalize {
+      filename = normalize(&filename);
+    }
+    if self.lowercase {
+      filename = filename.to_lowercase();
+    }
+    if self.prefix.is_some() || self.suffix.is_some() {
+      filename = sanitize(&add_affixes(
+        &filename,
+        self.prefix.as_deref().unwrap_or_default(),
+        self.suffix.as_deref().unwrap_or_default(),
+        self.suffix_placement,
+      ));
+    }
+    filename
+  }
+
+  /// Prepare a `filename` given explicitly instead of derived, trimming it
+  /// like derived ones if enabled so the `.part` file and the final file
+  /// agree on the name
+  pub fn explicit(&self, filename: &str) -> String {
+    if !(self.trim || cfg!(windows)) {
+      return filename.to_string();
+    }
+    match trim(filename) {
+      "" => FALLBACK_FILENAME.to_string(),
+      trimmed => trimmed.to_string(),
+    }
+  }
+}
+
+/// Extensions spanning two dots, kept whole when placing a suffix before the
+/// extension
+const COMPOUND_EXTENSIONS: &[&str] =
+  &["tar.gz", "tar.bz2", "tar.xz", "tar.zst", "tar.lz"];
+
+/// Add `prefix` and `suffix` to `filename`, placing the suffix before the
+/// extension (compound ones like `tar.gz` included) or at the end.
+///
+/// Names without an extension, including dotfiles, get the suffix at the end.
+pub fn add_affixes(
+  filename: &str,
+  prefix: &str,
+  suffix: &str,
+  placement: SuffixPlacement,
+) -> String {
+  let (stem, ext) = match placement {
+    SuffixPlacement::End => (filename, ""),
+    SuffixPlacement::BeforeExt => {
+      let lower = filename.to_lowercase();
+      let ext_len = COMPOUND_EXTENSIONS
+        .iter()
+        .find(|ext| lower.ends_with(&format!(".{}", ext)))
+        .map(|ext| ext.len() + 1)
+        .or_else(|| filename.rfind('.').map(|i| filename.len() - i))
+        .filter(|&len| len < filename.len());
+      match ext_len {
+        Some(len) => filename.split_at(filename.len() - len),
+        None => (filename, ""),
+      }
+    },
+  };
+  format!("{}{}{}{}", prefix, stem, suffix, ext)
+}
+
+/// Derive a filesystem-safe filename from the last path segment of `url`.
+///
+/// The query string and fragment are dropped unless `keep_query` or
+/// `keep_fragment` is set, in which case they are appended with a `_`
+/// separator. Strings that do not parse as URLs are handled the same way.
+pub fn from_url(url: &str, keep_query: bool, keep_fragment: bool) -> String {
+  let (path, query, fragment) = match Url::parse(url) {
+    Ok(u) => (
+      u.path_segments()
+        .and_then(|mut s| s.next_back().map(str::to_string))
+        .unwrap_or_default(),
+      u.query().map(str::to_string),
+      u.fragment().map(str::to_string),
+    ),
+    Err(_) => {
+      let (rest, fragment) = match url.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment.to_string())),
+        None => (url, None),
+      };
+      let (path, query) = match rest.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (rest, None),
+      };
+      let name = path.rsplit(['/', '\\']).next().unwrap_or_default();
+      (name.to_string(), query, fragment)
+    },
+  };
+
+  let mut filename = path;
+  if keep_query && let Some(query) = query.filter(|q| !q.is_empty()) {
+    filename.push('_');
+    filename.push_str(&query);
+  }
+  if keep_fragment && let Some(fragment) = fragment.filter(|f| !f.is_empty()) {
+    filename.push('_');
+    filename.push_str(&fragment);
+  }
+  sanitize(&filename)
+}
+
+/// Directories of the path of `url`, all segments but the last, without
+/// empty, `.` and `..` segments
+fn dirs_of(url: &str) -> Vec<String> {
+  let Ok(url) = Url::parse(url) else {
+    return Vec::new();
+  };
+  let mut dirs = url
+    .path_segments()
+    .map(|segments| segments.map(str::to_string).collect::<Vec<_>>())
+    .unwrap_or_default();
+  dirs.pop();
+  dirs.retain(|dir| !matches!(dir.as_str(), "" | "." | ".."));
+  dirs
+}
+
+/// Filename from a `Content-Disposition` header value, preferring the RFC 5987
+/// `filename*=` parameter over `filename=`.
+///
+/// The name is sanitized, so separators in a malicious header cannot point
+/// outside the destination directory.
+pub fn from_content_disposition(value: &str) -> Option<String> {
+  let mut plain = None;
+  let mut extended = None;
+  for param in value.split(';').skip(1) {
+    let Some((key, value)) = param.split_once('=') else {
+      continue;
+    };
+    let value = value.trim();
+    match key.trim().to_ascii_lowercase().as_str() {
+      "filename" => plain = Some(value.trim_matches('"').replace("\\\"", "\"")),
+      "filename*" => extended = decode_ext_value(value),
+      _ => (),
+    }
+  }
+  extended
+    .or(plain)
+    .filter(|name| !name.trim().is_empty())
+    .map(|name| sanitize(&name))
+}
+
+/// Decode an RFC 5987 `charset'language'value`, UTF-8 and ISO-8859-1 only
+fn decode_ext_value(value: &str) -> Option<String> {
+  let mut parts = value.trim_matches('"').splitn(3, '\'');
+  let charset = parts.next()?.to_ascii_lowercase();
+  let encoded = parts.nth(1)?;
+  let bytes = percent_encoding::percent_decode_str(encoded);
+  match charset.as_str() {
+    "utf-8" => Some(bytes.decode_utf8_lossy().into_owned()),
+    "iso-8859-1" => Some(bytes.map(char::from).collect()),
+    _ => None,
+  }
+}
+
+/// Make `filename` safe to create on common filesystems.
+///
+/// Path separators, characters reserved on Windows and control characters
+/// are replaced with `_`, trailing dots and spaces are trimmed and names
+/// longer than 255 bytes are shortened, keeping a short extension. Empty
+/// results become [`FALLBACK_FILENAME`].
+pub fn sanitize(filename: &str) -> String {
+  let replaced = filename
+    .chars()
+    .map(|c| match c {
+      '/' | '\\' | '?' | '#' | '<' | '>' | ':' | '"' | '|' | '*' => '_',
+      c if c.is_control() => '_',
+      c => c,
+    })
+    .collect::<String>();
+  let mut sanitized = trim(&replaced).to_string();
+
+  if sanitized.len() > MAX_FILENAME_BYTES {
+    let ext = sanitized
+      .rsplit_once('.')
+      .map(|(_, ext)| format!(".{}", ext))
+      .filter(|ext| ext.len() <= 16)
+      .unwrap_or_default();
+    let mut end = MAX_FILENAME_BYTES - ext.len();
+    while !sanitized.is_char_boundary(end) {
+      end -= 1;
+    }
+    sanitized.truncate(end);
+    // The cut may leave a dot or space at the end
+    sanitized.truncate(trim(&sanitized).len());
+    sanitized.push_str(&ext);
+  }
+
+  if sanitized.is_empty() || sanitized == "." || sanitized == ".." {
+    FALLBACK_FILENAME.to_string()
+  } else {
+    sanitized
+  }
+}
+
+/// Trim surrounding whitespace and trailing dots from `filename`, which
+/// Windows strips and would make a file unreachable under the name it was
+/// created with
+pub fn trim(filename: &str) -> &str {
+  filename.trim().trim_end_matches(['.', ' '])
+}
+
+/// Join `filename`, derived from `url`, onto `dest`.
+///
+/// Names that are not made of plain path components only, like `..` or
+/// absolute paths, could resolve outside `dest` and are rejected.
+pub fn join_within(dest: &Path, filename: &str, url: &str) -> Result<PathBuf> {
+  let path = Path::new(filename);
+  let plain = path.components().all(|c| matches!(c, Component::Normal(_)));
+  match path.components().next() {
+    Some(_) if plain => Ok(dest.join(filename)),
+    _ => Err(DownloadError::InvalidUrlError(format!(
+      "{} resolves to a path outside the destination: {:?}",
+      url, filename
+    ))),
+  }
+}
+
+/// Whether `filename` ends with any of `extensions` (case-insensitive, with
+/// or without a leading dot), multi-part extensions like `tar.gz` included
+pub fn has_extension(filename: &str, extensions: &[String]) -> bool {
+  let filename = filename.to_lowercase();
+  extensions.iter().any(|ext| {
+    let ext = ext.trim().trim_start_matches('.').to_lowercase();
+    !ext.is_empty() && filename.ends_with(&format!(".{}", ext))
+  })
+}
+
+/// Percent-decode, NFC-normalize and trim `filename` so names authored with
+/// different Unicode forms or encodings map to the same local file.
+///
+/// Unsafe characters produced by decoding are replaced as in [`sanitize`].
+pub fn normalize(filename: &str) -> String {
+  let decoded =
+    percent_encoding::percent_decode_str(filename).decode_utf8_lossy();
+  sanitize(&decoded.nfc().collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn normalize_composes_to_nfc() {
+    let composed = "caf\u{e9}.txt";
+    assert_eq!(normalize("cafe\u{301}.txt"), composed);
+    assert_eq!(normalize(composed), composed);
+    assert_eq!(normalize("caf%C3%A9.txt"), composed);
+    assert_eq!(normalize("cafe%CC%81.txt"), composed);
+  }
+
+  #[test]
+  fn normalize_trims_and_sanitizes_decoded_names() {
+    assert_eq!(normalize("  report.pdf  "), "report.pdf");
+    assert_eq!(normalize("%20report.pdf%20"), "report.pdf");
+    assert_eq!(normalize("a%2Fb.txt"), "a_b.txt");
+    assert_eq!(normalize("%20%20"), FALLBACK_FILENAME);
+  }
+
+  #[test]
+  fn apply_lowercases_after_normalizing() {
+    let opts = FilenameOptions {
+      lowercase: true,
+      normalize: true,
+      ..Default::default()
+    };
+    assert_eq!(opts.apply("CAFE\u{301}.TXT".to_string()), "caf\u{e9}.txt");
+    assert_eq!(opts.derive("https://example.com/Dir/README.MD"), "readme.md");
+    let opts = FilenameOptions {
+      lowercase: true,
+      ..Default::default()
+    };
+    assert_eq!(opts.apply("Mixed%20Case.TXT".to_string()), "mixed%20case.txt");
+  }
+
+  #[test]
+  fn from_url_strips_long_queries_and_fragments() {
+    let url =
+      format!("https://example.com/file.zip?token={}#x", "a".repeat(2000));
+    assert_eq!(from_url(&url, false, false), "file.zip");
+    assert_eq!(from_url("file.txt?x=1#y", false, false), "file.txt");
+    assert_eq!(
+      from_url("https://example.com/?x=1", false, false),
+      FALLBACK_FILENAME
+    );
+  }
+
+  #[test]
+  fn from_url_keeps_long_queries_within_filename_limits() {
+    let url =
+      format!("https://example.com/file.zip?token={}", "a".repeat(2000));
+    let name = from_url(&url, true, false);
+    assert_eq!(name.len(), MAX_FILENAME_BYTES);
+    assert!(name.starts_with("file.zip_token=aaa"));
+
+    // A short extension at the end of the query survives the cut
+    let url = format!("https://example.com/dl?name={}.tar", "x".repeat(300));
+    let name = from_url(&url, true, false);
+    assert_eq!(name.len(), MAX_FILENAME_BYTES);
+    assert!(name.starts_with("dl_name=x") && name.ends_with("x.tar"));
+  }
+
+  #[test]
+  fn from_url_makes_kept_queries_safe() {
+    let name = from_url("https://example.com/get?path=/a/b?c#d:e", true, true);
+    assert_eq!(name, "get_path=_a_b_c_d_e");
+    assert_eq!(from_url("file.txt?x=1#y", true, false), "file.txt_x=1");
+    assert_eq!(from_url("file.txt?#", true, true), "file.txt");
+  }
+
+  #[test]
+  fn suffix_goes_before_the_extension() {
+    let before =
+      |name| add_affixes(name, "", "-v2", SuffixPlacement::BeforeExt);
+    assert_eq!(before("file.bin"), "file-v2.bin");
+    assert_eq!(before("archive.tar.gz"), "archive-v2.tar.gz");
+    assert_eq!(before("ARCHIVE.TAR.XZ"), "ARCHIVE-v2.TAR.XZ");
+    assert_eq!(before("report.final.pdf"), "report.final-v2.pdf");
+  }
+
+  #[test]
+  fn suffix_without_an_extension_goes_at_the_end() {
+    let before =
+      |name| add_affixes(name, "", "-v2", SuffixPlacement::BeforeExt);
+    assert_eq!(before("README"), "README-v2");
+    assert_eq!(before(".bashrc"), ".bashrc-v2");
+    assert_eq!(before(".tar.gz"), ".tar.gz-v2");
+    // Dotfiles with an extension of their own keep it
+    assert_eq!(before(".config.json"), ".config-v2.json");
+  }
+
+  #[test]
+  fn affixes_at_the_end_and_with_prefix() {
+    assert_eq!(
+      add_affixes("file.tar.gz", "2024-", ".bak", SuffixPlacement::End),
+      "2024-file.tar.gz.bak"
+    );
+    assert_eq!(
+      add_affixes("README", "old-", "", SuffixPlacement::BeforeExt),
+      "old-README"
+    );
+    let opts = FilenameOptions {
+      prefix: Some("a/b-".to_string()),
+      suffix: Some("-x".to_string()),
+      suffix_placement: SuffixPlacement::BeforeExt,
+      ..Default::default()
+    };
+    assert_eq!(opts.derive("https://example.com/data.csv"), "a_b-data-x.csv");
+  }
+
+  /// Names that try to escape the destination directory
+  const TRAVERSALS: &[&str] = &[
+    "../x",
+    "../../etc/passwd",
+    "..",
+    "a/../../b",
+    "/etc/passwd",
+    r"..\..\win.ini",
+    r"C:\Windows\win.ini",
+    r"\\server\share\x",
+  ];
+
+  #[test]
+  fn derived_names_stay_in_the_destination() {
+    let urls = [
+      "https://host/../../etc/passwd",
+      "https://host/a/%2e%2e/%2e%2e/etc/passwd",
+      "https://host/%2e%2e%2f%2e%2e%2fetc%2fpasswd",
+      "https://host/..%5c..%5cwin.ini",
+      "https://host/a/b/%2e%2e",
+      "../../etc/passwd",
+      r"..\..\win.ini",
+    ];
+    let dest = Path::new("/downloads");
+    for normalize in [false, true] {
+      for preserve_path in [false, true] {
+        let opts = FilenameOptions {
+          normalize,
+          preserve_path,
+          ..Default::default()
+        };
+        for url in urls {
+          let name = opts.derive(url);
+          let path = join_within(dest, &name, url).unwrap();
+          assert!(path.starts_with(dest), "{} -> {:?}", url, path);
+          assert!(!name.split('/').any(|part| part == ".." || part.is_empty()));
+        }
+      }
+    }
+    assert_eq!(
+      from_url("https://host/../../etc/passwd", false, false),
+      "passwd"
+    );
+    let opts = FilenameOptions {
+      normalize: true,
+      ..Default::default()
+    };
+    assert_eq!(
+      opts.derive("https://host/%2e%2e%2f%2e%2e%2fetc%2fpasswd"),
+      ".._.._etc_passwd"
+    );
+  }
+
+  #[test]
+  fn sanitize_removes_separators_and_dot_names() {
+    for name in TRAVERSALS {
+      let sanitized = sanitize(name);
+      assert!(!sanitized.contains(['/', '\\', ':']), "{:?}", sanitized);
+      assert_ne!(sanitized, "..");
+    }
+    assert_eq!(sanitize(".."), FALLBACK_FILENAME);
+    assert_eq!(sanitize("."), FALLBACK_FILENAME);
+    assert_eq!(sanitize("../x"), ".._x");
+    assert_eq!(sanitize(r"C:\Windows\win.ini"), "C__Windows_win.ini");
+  }
+
+  #[test]
+  fn join_within_rejects_escaping_names() {
+    let dest = Path::new("/downloads");
+    for name in ["../x", "..", "a/../../b", "/etc/passwd", "", "./x"] {
+      assert!(join_within(dest, name, "u").is_err(), "{:?}", name);
+    }
+    assert_eq!(
+      join_within(dest, "a/b.txt", "u").unwrap(),
+      Path::new("/downloads/a/b.txt")
+    );
+    // Drive and UNC prefixes only mean something on Windows, elsewhere they
+    // are plain names
+    for name in [r"C:\Windows\win.ini", r"\\server\share\x", r"..\x"] {
+      let joined = join_within(dest, name, "u");
+      assert_eq!(joined.is_err(), cfg!(windows), "{:?}", name);
+      if let Ok(path) = joined {
+        assert_eq!(path.parent(), Some(dest));
+      }
+    }
+  }
+
+  #[test]
+  fn content_disposition_cannot_traverse() {
+    let header = |value: &str| format!("attachment; {}", value);
+    for value in [
+      r#"filename="../../etc/passwd""#,
+      r#"filename="..\..\win.ini""#,
+      "filename*=UTF-8''%2e%2e%2f%2e%2e%2fetc%2fpasswd",
+      r#"filename="/etc/passwd""#,
+    ] {
+      let name = from_content_disposition(&header(value)).unwrap();
+      assert!(!name.contains(['/', '\\']), "{:?}", name);
+      assert!(join_within(Path::new("/downloads"), &name, "u").is_ok());
+    }
+    assert_eq!(
+      from_content_disposition(&header(r#"filename="..""#)).as_deref(),
+      Some(FALLBACK_FILENAME)
+    );
+  }
+
+  #[test]
+  fn trim_strips_trailing_dots_and_spaces() {
+    assert_eq!(trim("report.pdf. . "), "report.pdf");
+    assert_eq!(trim("  report.pdf"), "report.pdf");
+    assert_eq!(trim("name..."), "name");
+    assert_eq!(trim(". ."), "");
+    // Leading dots make dotfiles, inner ones separate extensions
+    assert_eq!(trim(".hidden. "), ".hidden");
+    assert_eq!(trim("a. b.txt"), "a. b.txt");
+  }
+
+  #[test]
+  fn derived_names_never_end_in_dots_or_spaces() {
+    let opts = FilenameOptions::default();
+    assert_eq!(opts.derive("https://host/file.txt."), "file.txt");
+    assert_eq!(opts.derive("https://host/file.txt%20"), "file.txt%20");
+    let opts = FilenameOptions {
+      normalize: true,
+      ..Default::default()
+    };
+    assert_eq!(opts.derive("https://host/file.txt%20.%20"), "file.txt");
+    assert_eq!(
+      from_content_disposition(r#"attachment; filename="notes. . ""#)
+        .as_deref(),
+      Some("notes")
+    );
+  }
+
+  #[test]
+  fn explicit_names_are_trimmed_when_enabled() {
+    let keep = FilenameOptions::default();
+    let trim = FilenameOptions {
+      trim: true,
+      ..Default::default()
+    };
+    if !cfg!(windows) {
+      assert_eq!(keep.explicit("out.bin. "), "out.bin. ");
+    }
+    assert_eq!(trim.explicit("out.bin. "), "out.bin");
+    assert_eq!(trim.explicit(" . "), FALLBACK_FILENAME);
+    // The partial is named after the trimmed name, so both agree
+    assert_eq!(trim.explicit(&trim.explicit("out.bin..")), "out.bin");
+  }
+
+  #[test]
+  fn truncation_does_not_leave_a_trailing_dot() {
+    // The cut lands right after a dot, which is trimmed before the
+    // extension goes back on
+    let name = format!("{}.{}.json", "a".repeat(249), "b".repeat(10));
+    let sanitized = sanitize(&name);
+    assert_eq!(sanitized, format!("{}.json", "a".repeat(249)));
+    assert!(sanitized.len() <= MAX_FILENAME_BYTES);
+    let name = format!("{} {}", "a".repeat(254), "b".repeat(10));
+    assert_eq!(sanitize(&name), "a".repeat(254));
+  }
+}
@@ -0,0 +1,3200 @@
+//! A concurrent and configurable multi-file downloader.
+//!
+//! Configure a [`Downloader`] with its `with_*` builder methods, validate it
+//! with [`Downloader::build`] and start it with [`Downloader::run`]:
+//!
+//! ```no_run
+//! # async fn download() -> multifiledownloader::error::Result<()> {
+//! use multifiledownloader::{client::ClientOptions, Downloader};
+//!
+//! let summary = Downloader::new(
+//!   vec!["https://example.com/file.tar.gz".to_string()],
+//!   "downloads".to_string(),
+//!   4,
+//!   false,
+//!   ClientOptions::default(),
+//! )?
+//! .with_extract(true)
+//! .build()?
+//! .run()
+//! .await?;
+//! println!("{} files downloaded", summary.downloaded);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod breaker;
+pub mod checksum;
+pub mod cli;
+pub mod client;
+pub mod crawl;
+pub mod doh;
+pub mod error;
+pub mod extract;
+pub mod filename;
+pub mod lock;
+#[cfg(feature = "pac")]
+pub mod pac;
+pub mod plan;
+pub mod progress;
+pub mod provenance;
+pub mod ratelimit;
+pub mod report;
+pub mod select;
+pub mod summary;
+pub mod utils;
+pub mod webhook;
+
+shadow_rs::shadow!(build);
+
+use std::{
+  collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+  fs::{self, File},
+  io::Write,
+  path::PathBuf,
+  sync::{atomic::Ordering, Arc},
+};
+
+use futures::StreamExt;
+use indicatif::{
+  MultiProgress,
+  MultiProgressAlignment,
+  ProgressBar,
+  ProgressDrawTarget,
+  ProgressStyle,
+};
+use reqwest::Client;
+use tokio::{
+  sync::{mpsc, Semaphore},
+  task,
+};
+use tracing::info;
+use url::Url;
+
+use crate::{
+  breaker::CircuitBreaker,
+  checksum::{Algorithm, Hasher},
+  cli::{DedupMode, ExistingPolicy, SizeOrder},
+  client::{Auth, ClientOptions},
+  crawl::CrawlOptions,
+  error::Result,
+  filename::FilenameOptions,
+  lock::{LockEntry, Lockfile, ManifestLock},
+  plan::{Plan, PlanEntry, PlannedAction},
+  progress::ProgressEvent,
+  ratelimit::{RateLimiter, RateSchedule, RateShare},
+  report::{
+    DownloadEvent,
+    DownloadOutcome,
+    DownloadResult,
+    OnComplete,
+    ResponseRecord,
+    ResultsLog,
+  },
+  summary::{RetryCounters, RetryReason, RunCounters, RunSummary},
+  webhook::{Webhook, WebhookEvent},
+};
+
+/// Redirects followed when tracing a URL, reqwest's own default limit
+const MAX_REDIRECTS: usize = 10;
+
+/// Files smaller than this, in bytes, are not split by default
+pub const DEFAULT_SPLIT_THRESHOLD: u64 = 8 << 20;
+
+/// Smallest range, in bytes, split downloads are cut into by default
+pub const DEFAULT_MIN_CHUNK_SIZE: u64 = 1 << 20;
+
+// Struct to hold downloader configuration and state
+#[derive(Clone)]
+pub struct Downloader {
+  urls:       Vec<String>,
+  dest:       PathBuf,
+  workers:    usize,
+  client:     Client,
+  total_size: Arc<tokio::sync::Mutex<u64>>,
+  clean:      bool,
+  seen_urls:  Arc<tokio::sync::Mutex<HashSet<String>>>,
+
+  filename_opts:      FilenameOptions,
+  output_names:       HashMap<String, String>,
+  unique_names:       HashMap<String, String>,
+  claimed_names:      Arc<std::sync::Mutex<HashMap<String, String>>>,
+  disposition_names:  Arc<std::sync::Mutex<HashMap<String, String>>>,
+  dest_is_file:       bool,
+  sort_by_size:       Option<SizeOrder>,
+  only_extensions:    Vec<String>,
+  exclude_extensions: Vec<String>,
+  skip_list:          HashSet<String>,
+  resume_from:        Option<PathBuf>,
+  resume_index:       usize,
+  crawl:              Option<CrawlOptions>,
+  url_stream:         Option<Arc<tokio::sync::Mutex<mpsc::Receiver<String>>>>,
+  interleave_hosts:   bool,
+  concurrency_ramp:   Option<std::time::Duration>,
+  per_host:           Option<usize>,
+  host_permits:       Arc<std::sync::Mutex<HashMap<String, Arc<Semaphore>>>>,
+
+  refresh_cmd:     Option<String>,
+  auth:            Option<Auth>,
+  head_timeout:    Option<std::time::Duration>,
+  redirect_client: Option<Client>,
+  read_chunk_size: Option<usize>,
+  write_permits:   Option<Arc<Semaphore>>,
+  rate_limiter:    Arc<RateLimiter>,
+  rate_schedule:   Option<RateSchedule>,
+  max_rate:        Option<u64>,
+  split:           usize,
+  split_threshold: u64,
+  min_chunk_size:  u64,
+  ranged_urls:     Arc<tokio::sync::Mutex<HashSet<String>>>,
+  trace_chunks:    bool,
+
+  existing:                 ExistingPolicy,
+  etag_cache:               bool,
+  etags:                    Arc<tokio::sync::Mutex<HashMap<String, String>>>,
+  dedup_by_etag:            bool,
+  dedup_content:            Option<DedupMode>,
+  resume_metadata_ttl:      Option<std::time::Duration>,
+  resume_partial_threshold: Option<u64>,
+  resume_check_remote:      bool,
+
+  retries:                   Arc<RetryCounters>,
+  max_retries:               u32,
+  retry_backoff:             std::time::Duration,
+  timeout_retries:           u32,
+  checksum_retries:          u32,
+  disk_retries:              u32,
+  throttled:                 Arc<tokio::sync::Mutex<HashMap<String, usize>>>,
+  max_response_header_bytes: Option<u64>,
+  max_idle_time:             Option<std::time::Duration>,
+  last_activity:             Arc<std::sync::Mutex<std::time::Instant>>,
+  circuit_breaker:           Option<Arc<CircuitBreaker>>,
+  health_interval:           Option<std::time::Duration>,
+  fail_fast:                 bool,
+
+  expected_checksums:     HashMap<String, String>,
+  remote_checksum_suffix: Option<String>,
+  provenance:             bool,
+  file_mode:              Option<u32>,
+  fsync:                  bool,
+  manifest_lock:          Option<ManifestLock>,
+  lock_entries:           Arc<tokio::sync::Mutex<BTreeMap<String, LockEntry>>>,
+  extract:                bool,
+  extract_permits:        Arc<Semaphore>,
+  extraction_failures:    Arc<std::sync::atomic::AtomicUsize>,
+
+  progress:        bool,
+  events:          Option<mpsc::Sender<ProgressEvent>>,
+  on_complete:     Option<OnComplete>,
+  webhook:         Option<Arc<Webhook>>,
+  results_log:     Option<ResultsLog>,
+  counters:        Arc<RunCounters>,
+  include_headers: bool,
+  responses:       Arc<std::sync::Mutex<HashMap<String, ResponseRecord>>>,
+}
+
+impl std::fmt::Debug for Downloader {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let truncate = if self.urls.len() > 3 {
+      3
+    } else {
+      self.urls.len()
+    };
+    let urls = self.urls.clone().into_iter().take(truncate).collect::<Vec<_>>();
+    let urls = format!(
+      "[{}{}; {}]",
+      urls.join(", "),
+      if self.urls.len() > truncate {
+        "..."
+      } else {
+        ""
+      },
+      self.urls.len()
+    );
+    f.debug_struct("Downloader")
+      .field("urls", &urls)
+      .field("dest", &self.dest)
+      .field("workers", &self.workers)
+      .field("total_size", &self.total_size)
+      .field("clean", &self.clean)
+      .field("refresh_cmd", &self.refresh_cmd)
+      .field("dedup_content", &self.dedup_content)
+      .field("read_chunk_size", &self.read_chunk_size)
+      .field("resume_metadata_ttl", &self.resume_metadata_ttl)
+      .field("filename_opts", &self.filename_opts)
+      .field("provenance", &self.provenance)
+      .field("rate_limiter", &self.rate_limiter)
+      .field("rate_schedule", &self.rate_schedule)
+      .field("circuit_breaker", &self.circuit_breaker)
+      .field("sort_by_size", &self.sort_by_size)
+      .field("only_extensions", &self.only_extensions)
+      .field("exclude_extensions", &self.exclude_extensions)
+      .field("resume_from", &self.resume_from)
+      .field("crawl", &self.crawl)
+      .field("resume_partial_threshold", &self.resume_partial_threshold)
+      .field("file_mode", &self.file_mode.map(|m| format!("{:o}", m)))
+      .field("head_timeout", &self.head_timeout)
+      .field("manifest_lock", &self.manifest_lock)
+      .field("extract", &self.extract)
+      .field("resume_check_remote", &self.resume_check_remote)
+      .field("disk_retries", &self.disk_retries)
+      .field("remote_checksum_suffix", &self.remote_checksum_suffix)
+      .field("expected_checksums", &self.expected_checksums)
+      .field("interleave_hosts", &self.interleave_hosts)
+      .field("webhook", &self.webhook)
+      .field("max_idle_time", &self.max_idle_time)
+      .field("concurrency_ramp", &self.concurrency_ramp)
+      .field("timeout_retries", &self.timeout_retries)
+      .field("auth", &self.auth)
+      .field("max_rate", &self.max_rate)
+      .field("resume_index", &self.resume_index)
+      .field("split", &self.split)
+      .field("dedup_by_etag", &self.dedup_by_etag)
+      .field("trace_chunks", &self.trace_chunks)
+      .field("max_response_header_bytes", &self.max_response_header_bytes)
+      .field("existing", &self.existing)
+      .field("etag_cache", &self.etag_cache)
+      .field("url_stream", &self.url_stream.is_some())
+      .field("results_log", &self.results_log)
+      .field("fsync", &self.fsync)
+      .field("fail_fast", &self.fail_fast)
+      .field("max_retries", &self.max_retries)
+      .field("retry_backoff", &self.retry_backoff)
+      .field("dest_is_file", &self.dest_is_file)
+      .field("output_names", &self.output_names)
+      .field("health_interval", &self.health_interval)
+      .field("checksum_retries", &self.checksum_retries)
+      .field("per_host", &self.per_host)
+      .field("unique_names", &self.unique_names)
+      .field("progress", &self.progress)
+      .field("on_complete", &self.on_complete.as_ref().map(|_| "<callback>"))
+      .field("include_headers", &self.include_headers)
+      .field("split_threshold", &self.split_threshold)
+      .field("min_chunk_size", &self.min_chunk_size)
+      .finish()
+  }
+}
+
+impl Default for Downloader {
+  fn default() -> Self {
+    Self {
+      urls:       Default::default(),
+      dest:       PathBuf::from(".")
+        .canonicalize()
+        .unwrap_or_else(|_| PathBuf::from(".")),
+      workers:    std::thread::available_parallelism().unwrap().get(),
+      client:     Default::default(),
+      total_size: Default::default(),
+      clean:      true,
+      seen_urls:  Default::default(),
+
+      filename_opts:      Default::default(),
+      output_names:       Default::default(),
+      unique_names:       Default::default(),
+      claimed_names:      Default::default(),
+      disposition_names:  Default::default(),
+      dest_is_file:       false,
+      sort_by_size:       None,
+      only_extensions:    Vec::new(),
+      exclude_extensions: Vec::new(),
+      skip_list:          Default::default(),
+      resume_from:        None,
+      resume_index:       0,
+      crawl:              None,
+      url_stream:         None,
+      interleave_hosts:   false,
+      concurrency_ramp:   None,
+      per_host:           None,
+      host_permits:       Default::default(),
+
+      refresh_cmd:     None,
+      auth:            None,
+      head_timeout:    None,
+      redirect_client: None,
+      read_chunk_size: None,
+      write_permits:   None,
+      rate_limiter:    Default::default(),
+      rate_schedule:   None,
+      max_rate:        None,
+      split:           1,
+      split_threshold: DEFAULT_SPLIT_THRESHOLD,
+      min_chunk_size:  DEFAULT_MIN_CHUNK_SIZE,
+      ranged_urls:     Default::default(),
+      trace_chunks:    false,
+
+      existing:                 ExistingPolicy::Skip,
+      etag_cache:               true,
+      etags:                    Default::default(),
+      dedup_by_etag:            false,
+      dedup_content:            None,
+      resume_metadata_ttl:      None,
+      resume_partial_threshold: None,
+      resume_check_remote:      false,
+
+      retries:                   Default::default(),
+      max_retries:               0,
+      retry_backoff:             std::time::Duration::from_millis(500),
+      timeout_retries:           0,
+      checksum_retries:          0,
+      disk_retries:              0,
+      throttled:                 Default::default(),
+      max_response_header_bytes: None,
+      max_idle_time:             None,
+      last_activity:             Arc::new(std::sync::Mutex::new(
+        std::time::Instant::now(),
+      )),
+      circuit_breaker:           None,
+      health_interval:           None,
+      fail_fast:                 false,
+
+      expected_checksums:     HashMap::new(),
+      remote_checksum_suffix: None,
+      provenance:             false,
+      file_mode:              None,
+      fsync:                  false,
+      manifest_lock:          None,
+      lock_entries:           Default::default(),
+      extract:                false,
+      extract_permits:        Arc::new(Semaphore::new(
+        extract::default_concurrency(),
+      )),
+      extraction_failures:    Default::default(),
+
+      progress:        true,
+      events:          None,
+      on_complete:     None,
+      webhook:         None,
+      results_log:     None,
+      counters:        Default::default(),
+      include_headers: false,
+      responses:       Default::default(),
+    }
+  }
+}
+
+/// Downloader implementation
+impl Downloader {
+  /// Create a new Downloader with a client built from `client_opts`
+  pub fn new(
+    urls: Vec<String>,
+    dest: String,
+    workers: usize,
+    clean: bool,
+    client_opts: ClientOptions,
+  ) -> Result<Self> {
+    let dest = shellexpand::tilde(&dest).to_string();
+    let dest = PathBuf::from(dest.clone())
+      .canonicalize()
+      .unwrap_or_else(|_| PathBuf::from(dest.clone()));
+    Ok(Downloader {
+      urls,
+      dest,
+      workers,
+      client: client_opts.build()?,
+      clean,
+      ..Default::default()
+    })
+  }
+
+  /// Get the filename from a given URL.
+  /// If the URL is invalid or the url has no path segments, return
+  /// "downloaded_file"
+  pub fn get_filename(url: &str) -> String {
+    filename::from_url(url, false, false)
+  }
+
+  /// Get the local filename for `url`: its explicit output name if it has
+  /// one, otherwise the configured filename normalizations applied to the
+  /// name from its `Content-Disposition` header when one was seen, or to
+  /// [`Self::get_filename`]
+  pub fn local_filename(&self, url: &str) -> String {
+    if let Some(name) = self.output_names.get(url) {
+      return self.filename_opts.explicit(name);
+    }
+    if let Some(name) = self.disposition_names.lock().unwrap().get(url) {
+      return name.clone();
+    }
+    match self.unique_names.get(url) {
+      Some(name) => name.clone(),
+      None => self.filename_opts.derive(url),
+    }
+  }
+
+  /// Path `filename`, derived from `url`, is downloaded to, refusing names
+  /// that would escape `self.dest` and creating the directories of nested
+  /// ones
+  fn local_path(&self, url: &str, filename: &str) -> Result<PathBuf> {
+    let path = filename::join_within(&self.dest, filename, url)?;
+    if let Some(parent) = path.parent()
+      && parent != self.dest
+    {
+      fs::create_dir_all(parent)?;
+    }
+    // Long paths need the extended-length prefix on Windows
+    Ok(utils::long_path(&path))
+  }
+
+  /// Shell Expand tilde in string
+  pub fn shellexpand_tilde<T: AsRef<str>>(s: T) -> String {
+    shellexpand::tilde(s.as_ref()).to_string()
+  }
+
+  /// Shell Expand environment variables and tilde home directory in string
+  pub fn shellexpand_full<T: AsRef<str>>(s: T) -> String {
+    use std::{borrow::Cow, env};
+    fn context(s: &str) -> Option<Cow<'static, str>> {
+      match env::var(s) {
+        Ok(value) => Some(value.into()),
+        Err(env::VarError::NotPresent) => Some("".into()),
+        Err(_) => Some("".into()),
+      }
+    }
+    fn home_dir() -> Option<String> {
+      env::var("HOME").ok()
+    }
+    shellexpand::full_with_context_no_errors(s.as_ref(), home_dir, context)
+      .to_string()
+  }
+
+  pub fn num_workers(&self) -> usize {
+    self.workers
+  }
+
+  /// Validate the configuration, the last step before [`Downloader::run`]
+  /// or [`Downloader::plan`].
+  ///
+  /// Fails without workers, runs would wait for one forever.
+  pub fn build(self) -> Result<Self> {
+    let invalid =
+      |reason: &str| Err(error::DownloadError::ConfigError(reason.to_string()));
+    if self.workers == 0 {
+      return invalid("at least one worker is required");
+    }
+    Ok(self)
+  }
+
+  /// Get the number of URLs
+  pub fn num_urls(&self) -> usize {
+    self.urls.len()
+  }
+
+  pub fn get_dest(&self) -> PathBuf {
+    self.dest.clone()
+  }
+
+  /// Set the list of URLs
+  pub fn with_urls(mut self, urls: Vec<String>) -> Self {
+    self.urls = urls
+      .into_iter()
+      .map(|s| s.trim().to_string())
+      .filter(|s| !s.is_empty())
+      .filter_map(|s| Url::parse(&s).ok().map(|u| u.to_string()))
+      .collect::<Vec<_>>();
+    self
+  }
+
+  /// Set the number of worker threads
+  pub fn with_workers(mut self, workers: usize) -> Self {
+    self.workers = workers;
+    self
+  }
+
+  /// Set the destination directory
+  pub fn with_dest<T: AsRef<str>>(mut self, dest: T) -> Self {
+    let dest = shellexpand::tilde(dest.as_ref()).to_string();
+    let dest = PathBuf::from(dest.clone())
+      .canonicalize()
+      .unwrap_or_else(|_| PathBuf::from(dest.clone()));
+    self.dest = dest;
+    self
+  }
+
+  /// Set the reqwest client
+  pub fn with_client(mut self, client: Client) -> Self {
+    self.client = client;
+    self
+  }
+
+  /// Trace the redirects of each URL in [`Downloader::plan`] with `client`,
+  /// which must not follow redirects itself
+  pub fn with_redirect_client(mut self, client: Option<Client>) -> Self {
+    self.redirect_client = client;
+    self
+  }
+
+  /// Enable file cleanup
+  pub fn clean(mut self) -> Self {
+    self.clean = true;
+    self
+  }
+
+  /// Disable file cleanup
+  pub fn no_clean(mut self) -> Self {
+    self.clean = false;
+    self
+  }
+
+  /// Set the shell command used to regenerate expired presigned URLs
+  pub fn with_refresh_cmd(mut self, refresh_cmd: Option<String>) -> Self {
+    self.refresh_cmd = refresh_cmd;
+    self
+  }
+
+  /// Enable the post-download content deduplication pass
+  pub fn with_dedup_content(mut self, mode: Option<DedupMode>) -> Self {
+    self.dedup_content = mode;
+    self
+  }
+
+  /// Set the size writes and progress updates are batched to.
+  ///
+  /// Larger sizes reduce per-chunk overhead on servers that send many tiny
+  /// chunks at the cost of a less responsive progress bar; `None` writes
+  /// chunks as they arrive from the network.
+  pub fn with_read_chunk_size(mut self, size: Option<usize>) -> Self {
+    self.read_chunk_size = size;
+    self
+  }
+
+  /// Set the maximum age of a `.part` file that may still be resumed
+  pub fn with_resume_metadata_ttl(
+    mut self,
+    ttl: Option<std::time::Duration>,
+  ) -> Self {
+    self.resume_metadata_ttl = ttl;
+    self
+  }
+
+  /// Set how local filenames are derived from URLs
+  pub fn with_filename_options(mut self, opts: FilenameOptions) -> Self {
+    self.filename_opts = opts;
+    self
+  }
+
+  /// Enable writing `<file>.meta.json` provenance sidecars
+  pub fn with_provenance(mut self, provenance: bool) -> Self {
+    self.provenance = provenance;
+    self
+  }
+
+  /// Throttle downloads according to a time-of-day bandwidth schedule
+  pub fn with_rate_schedule(mut self, schedule: Option<RateSchedule>) -> Self {
+    self.rate_schedule = schedule;
+    self
+  }
+
+  /// Skip the remaining URLs of a host after `threshold` consecutive failures
+  /// to it, retrying the host once `cooldown` has passed
+  pub fn with_host_failure_threshold(
+    mut self,
+    threshold: Option<u32>,
+    cooldown: std::time::Duration,
+  ) -> Self {
+    self.circuit_breaker =
+      threshold.map(|t| Arc::new(CircuitBreaker::new(t, cooldown)));
+    self
+  }
+
+  /// Schedule downloads ordered by their size
+  pub fn with_sort_by_size(mut self, order: Option<SizeOrder>) -> Self {
+    self.sort_by_size = order;
+    self
+  }
+
+  /// Only download URLs whose derived filename has one of `extensions`,
+  /// an empty list allows all
+  pub fn with_only_extensions(mut self, extensions: Vec<String>) -> Self {
+    self.only_extensions = extensions;
+    self
+  }
+
+  /// Skip URLs whose derived filename has one of `extensions`
+  pub fn with_exclude_extensions(mut self, extensions: Vec<String>) -> Self {
+    self.exclude_extensions = extensions;
+    self
+  }
+
+  /// Set a fallback directory holding finished or partial downloads from an
+  /// earlier destination
+  pub fn with_resume_from(mut self, dir: Option<PathBuf>) -> Self {
+    self.resume_from = dir;
+    self
+  }
+
+  /// Crawl the URLs as HTML pages and download the files they link to
+  pub fn with_crawl(mut self, opts: Option<CrawlOptions>) -> Self {
+    self.crawl = opts;
+    self
+  }
+
+  /// Restart instead of resuming partial downloads smaller than `threshold`
+  /// bytes
+  pub fn with_resume_partial_threshold(
+    mut self,
+    threshold: Option<u64>,
+  ) -> Self {
+    self.resume_partial_threshold = threshold;
+    self
+  }
+
+  /// Set the Unix permissions of downloaded and partial files
+  pub fn with_file_mode(mut self, mode: Option<u32>) -> Self {
+    self.file_mode = mode;
+    self
+  }
+
+  /// Set the timeout of the HEAD requests used to size files
+  pub fn with_head_timeout(
+    mut self,
+    timeout: Option<std::time::Duration>,
+  ) -> Self {
+    self.head_timeout = timeout;
+    self
+  }
+
+  /// Write or, when frozen, verify a lock file of the downloads
+  pub fn with_manifest_lock(mut self, lock: Option<ManifestLock>) -> Self {
+    self.manifest_lock = lock;
+    self
+  }
+
+  /// Extract downloaded archives next to them
+  pub fn with_extract(mut self, extract: bool) -> Self {
+    self.extract = extract;
+    self
+  }
+
+  /// Discard partial downloads larger than the current remote file instead
+  /// of resuming them
+  pub fn with_resume_check_remote(mut self, check: bool) -> Self {
+    self.resume_check_remote = check;
+    self
+  }
+
+  /// Retry transient disk write errors (e.g. a momentarily full disk) this
+  /// many times before failing the download
+  pub fn with_disk_retries(mut self, retries: u32) -> Self {
+    self.disk_retries = retries;
+    self
+  }
+
+  /// Verify downloads against the SHA-256 digest published at `<url><suffix>`,
+  /// e.g. `.sha256`
+  pub fn with_remote_checksums(mut self, suffix: Option<String>) -> Self {
+    self.remote_checksum_suffix = suffix;
+    self
+  }
+
+  /// Verify downloads against expected SHA-256 digests, keyed by URL or
+  /// local filename, e.g. from [`checksum::parse_checksum_map`]
+  pub fn with_expected_checksums(
+    mut self,
+    checksums: HashMap<String, String>,
+  ) -> Self {
+    self.expected_checksums = checksums;
+    self
+  }
+
+  /// Round-robin the schedule across hosts so concurrent downloads are
+  /// spread over them
+  pub fn with_interleave_hosts(mut self, interleave: bool) -> Self {
+    self.interleave_hosts = interleave;
+    self
+  }
+
+  /// POST a JSON event to `webhook` after each download and the batch
+  pub fn with_webhook(mut self, webhook: Option<Webhook>) -> Self {
+    self.webhook = webhook.map(Arc::new);
+    self
+  }
+
+  /// Abort the run, keeping partial downloads, when no bytes were written
+  /// and no file completed for `max_idle`
+  pub fn with_max_idle_time(
+    mut self,
+    max_idle: Option<std::time::Duration>,
+  ) -> Self {
+    self.max_idle_time = max_idle;
+    self
+  }
+
+  /// Release worker permits gradually over `ramp` at startup instead of
+  /// opening all connections at once
+  pub fn with_concurrency_ramp(
+    mut self,
+    ramp: Option<std::time::Duration>,
+  ) -> Self {
+    self.concurrency_ramp = ramp;
+    self
+  }
+
+  /// Verify the file at `path` against `expected`, without downloading
+  /// anything
+  pub async fn verify(
+    path: &std::path::Path,
+    expected: &checksum::Checksum,
+  ) -> Result<bool> {
+    Ok(digest_file(path, expected.algorithm).await? == expected.digest)
+  }
+
+  /// Retry downloads that timed out `retries` times, resuming them
+  pub fn with_timeout_retries(mut self, retries: u32) -> Self {
+    self.timeout_retries = retries;
+    self
+  }
+
+  /// Download files failing checksum verification again, from scratch, up to
+  /// `retries` times
+  pub fn with_checksum_retries(mut self, retries: u32) -> Self {
+    self.checksum_retries = retries;
+    self
+  }
+
+  /// Retry downloads failing with transient errors up to `retries` times,
+  /// waiting `backoff` before the first retry and doubling it after each
+  pub fn with_retries(
+    mut self,
+    retries: u32,
+    backoff: std::time::Duration,
+  ) -> Self {
+    self.max_retries = retries;
+    self.retry_backoff = backoff;
+    self
+  }
+
+  /// Skip URLs, or URLs whose filename, is in `skip_list`
+  pub fn with_skip_list(mut self, skip_list: HashSet<String>) -> Self {
+    self.skip_list = skip_list;
+    self
+  }
+
+  /// Authenticate requests to the download URLs with `auth`
+  pub fn with_auth(mut self, auth: Option<Auth>) -> Self {
+    self.auth = auth;
+    self
+  }
+
+  /// Limit the aggregate throughput of all downloads to `rate` bytes per
+  /// second
+  pub fn with_max_rate(mut self, rate: Option<u64>) -> Self {
+    self.max_rate = rate;
+    self.rate_limiter = Arc::new(RateLimiter::new(rate));
+    self
+  }
+
+  /// Skip the first `index` URLs of the list
+  pub fn with_resume_index(mut self, index: usize) -> Self {
+    self.resume_index = index;
+    self
+  }
+
+  /// Download files whose server supports ranges in `segments` concurrent
+  /// byte ranges
+  pub fn with_split(mut self, segments: usize) -> Self {
+    self.split = segments.max(1);
+    self
+  }
+
+  /// Download files smaller than `bytes` over a single connection whatever
+  /// [`Self::with_split`]
+  pub fn with_split_threshold(mut self, bytes: u64) -> Self {
+    self.split_threshold = bytes;
+    self
+  }
+
+  /// Split files into fewer ranges than [`Self::with_split`] asks for when
+  /// ranges would be smaller than `bytes`
+  pub fn with_min_chunk_size(mut self, bytes: u64) -> Self {
+    self.min_chunk_size = bytes.max(1);
+    self
+  }
+
+  /// Download one URL per host and strong ETag, linking the others to it
+  pub fn with_dedup_by_etag(mut self, dedup: bool) -> Self {
+    self.dedup_by_etag = dedup;
+    self
+  }
+
+  /// Log every received chunk at trace level
+  pub fn with_trace_chunks(mut self, trace: bool) -> Self {
+    self.trace_chunks = trace;
+    self
+  }
+
+  /// Fail responses whose headers are larger than `max` bytes
+  pub fn with_max_response_header_bytes(mut self, max: Option<u64>) -> Self {
+    self.max_response_header_bytes = max;
+    self
+  }
+
+  /// Set what to do with files that already exist in the destination
+  pub fn with_existing_policy(mut self, policy: ExistingPolicy) -> Self {
+    self.existing = policy;
+    self
+  }
+
+  /// Cache ETags in `<file>.etag` sidecars to revalidate existing files with
+  /// `If-None-Match`
+  pub fn with_etag_cache(mut self, cache: bool) -> Self {
+    self.etag_cache = cache;
+    self
+  }
+
+  /// Also download URLs received from `stream` as they arrive, [`Self::run`]
+  /// completes once it closes
+  pub fn with_url_stream(
+    mut self,
+    stream: Option<mpsc::Receiver<String>>,
+  ) -> Self {
+    self.url_stream = stream.map(|s| Arc::new(tokio::sync::Mutex::new(s)));
+    self
+  }
+
+  /// Append the outcome of each URL to `log` as soon as it finishes
+  pub fn with_results_log(mut self, log: Option<ResultsLog>) -> Self {
+    self.results_log = log;
+    self
+  }
+
+  /// Sync downloads and their final rename to disk before reporting them
+  pub fn with_fsync(mut self, fsync: bool) -> Self {
+    self.fsync = fsync;
+    self
+  }
+
+  /// Abort the remaining downloads as soon as one fails
+  pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+    self.fail_fast = fail_fast;
+    self
+  }
+
+  /// Treat `dest` as the path of the output file of the single URL
+  pub fn with_dest_is_file(mut self, dest_is_file: bool) -> Self {
+    self.dest_is_file = dest_is_file;
+    self
+  }
+
+  /// Log aggregate progress every `interval`
+  pub fn with_health_interval(
+    mut self,
+    interval: Option<std::time::Duration>,
+  ) -> Self {
+    self.health_interval = interval;
+    self
+  }
+
+  /// Download at most `limit` files from the same host at once, `None` for
+  /// only the global worker limit
+  pub fn with_per_host(mut self, limit: Option<usize>) -> Self {
+    self.per_host = limit.filter(|&limit| limit > 0);
+    self
+  }
+
+  /// Save the URLs in `names` as the filename they map to instead of the
+  /// derived one
+  pub fn with_output_names(mut self, names: HashMap<String, String>) -> Self {
+    self.output_names.extend(names);
+    self
+  }
+
+  /// Write chunks of at most `limit` downloads to disk at once, `None` to
+  /// let every worker write whenever it has data
+  pub fn with_max_concurrent_writes(mut self, limit: Option<usize>) -> Self {
+    self.write_permits = limit
+      .filter(|&limit| limit > 0)
+      .map(|limit| Arc::new(Semaphore::new(limit)));
+    self
+  }
+
+  /// Render progress bars, on by default
+  pub fn with_progress(mut self, progress: bool) -> Self {
+    self.progress = progress;
+    self
+  }
+
+  /// Call `on_complete` as each download finishes, successfully or not.
+  ///
+  /// It runs on the download's task, hand long work off to another task or
+  /// thread.
+  pub fn with_on_complete<F>(mut self, on_complete: F) -> Self
+  where
+    F: Fn(&DownloadEvent) + Send + Sync + 'static,
+  {
+    self.on_complete = Some(Arc::new(on_complete));
+    self
+  }
+
+  /// Record the status and headers of the last response to each URL in its
+  /// [`DownloadResult`], sensitive headers redacted
+  pub fn with_include_headers_in_report(mut self, include: bool) -> Self {
+    self.include_headers = include;
+    self
+  }
+
+  /// Get the total size of all downloaded files
+  pub async fn get_total_size_bytes(&self) -> u64 {
+    *self.total_size.lock().await
+  }
+
+  /// Get the total size of all downloaded files
+  pub async fn get_total_size_human(&self) -> String {
+    human_readable_size(*self.total_size.lock().await)
+  }
+
+  /// Get file size of the file at `url` from http HEAD request
+  #[tracing::instrument(skip(self), fields(url), err(level = tracing::Level::ERROR))]
+  pub async fn get_file_size(&self, url: &str) -> Result<u64> {
+    let mut request = self.authorize(self.client.head(url));
+    if let Some(timeout) = self.head_timeout {
+      request = request.timeout(timeout);
+    }
+    let resp = request.send().await?;
+    self.record_response(url, &resp);
+    self.check_header_size(url, &resp)?;
+    // Retry on 429
+    if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+      self.record_throttle(url, &resp).await;
+      let random_t = rand::random_range(500..1500);
+      tokio::time::sleep(tokio::time::Duration::from_millis(random_t)).await;
+      return Box::pin(self.get_file_size(url)).await;
+    }
+    // HEAD responses carry no body, so probe with a GET to tell an expired
+    // presigned URL apart from a genuine permissions problem
+    if resp.status() == reqwest::StatusCode::FORBIDDEN {
+      let body = self
+        .authorize(self.client.get(url))
+        .header("Range", "bytes=0-0")
+        .send()
+        .await?
+        .text()
+        .await
+        .unwrap_or_default();
+      if is_presigned_url_expired(&body) {
+        return Err(error::DownloadError::PresignedUrlExpired(url.to_string()));
+      }
+    }
+    // Handle error
+    match resp.error_for_status_ref() {
+      Ok(_) => (),
+      Err(e) => return Err(error::DownloadError::ReqwestError(e)),
+    }
+    // Get content length from response or response headers
+    let content_len = resp
+      .headers()
+      .get("content-length")
+      .map(|v| v.to_str().unwrap().parse::<u64>().unwrap())
+      .or(resp.content_length())
+      .unwrap_or(0);
+    if resp
+      .headers()
+      .get("accept-ranges")
+      .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"bytes"))
+    {
+      self.ranged_urls.lock().await.insert(url.to_string());
+    }
+    // Weak ETags do not promise identical bytes
+    if let Some(etag) = resp.headers().get("etag").and_then(|v| v.to_str().ok())
+      && !etag.starts_with("W/")
+    {
+      self.etags.lock().await.insert(url.to_string(), etag.to_string());
+    }
+    self.record_disposition(url, resp.headers());
+    if resp.status().is_success() {
+      if !self.seen_urls.lock().await.contains(url) {
+        // Update total size and seen urls
+        self.seen_urls.lock().await.insert(url.to_string());
+        *self.total_size.lock().await += content_len;
+      }
+      return Ok(content_len);
+    }
+    Ok(content_len)
+  }
+
+  /// Probe the size of every URL with a HEAD request, `self.workers` at a
+  /// time and in input order, without counting them towards the run total.
+  ///
+  /// URLs whose size could not be determined map to `None`.
+  pub async fn presize(&self) -> Vec<(String, Option<u64>)> {
+    let sizes = futures::stream::iter(self.urls.clone())
+      .map(|url| async move {
+        let size = self.get_file_size(&url).await.ok();
+        (url, size)
+      })
+      .buffered(self.workers.max(1))
+      .collect::<Vec<_>>()
+      .await;
+    self.seen_urls.lock().await.clear();
+    *self.total_size.lock().await = 0;
+    sizes
+  }
+
+  /// URLs a run would download: the configured URLs, expanded by the crawl
+  /// and filtered and ordered by the extension and size options
+  async fn scheduled_urls(&self) -> Vec<String> {
+    let mut urls = self.urls.clone();
+    if self.resume_index > 0 {
+      let start = self.resume_index.min(urls.len());
+      info!(
+        "Starting at index {}, processing URLs {}..{} of {}",
+        self.resume_index,
+        start,
+        urls.len(),
+        urls.len()
+      );
+      urls.drain(..start);
+    }
+    if let Some(opts) = &self.crawl {
+      urls = crawl::crawl(&self.client, urls, opts).await;
+    }
+
+    // Drop URLs completed elsewhere
+    if !self.skip_list.is_empty() {
+      let before = urls.len();
+      urls.retain(|url| !self.is_skip_listed(url));
+      info!("Skipped {} URLs listed in the skip list", before - urls.len());
+    }
+
+    // Filter by extension of the derived filename
+    if !self.only_extensions.is_empty() || !self.exclude_extensions.is_empty() {
+      let before = urls.len();
+      urls.retain(|url| self.matches_extensions(url));
+      info!("Filtered out {} URLs by extension", before - urls.len());
+    }
+
+    // Order the schedule by size, files of unknown size go last
+    if let Some(order) = self.sort_by_size {
+      let mut sizes = self.clone().with_urls(urls).presize().await;
+      sizes.sort_by_key(|(_, size)| match (order, size) {
+        (_, None) => (1, 0),
+        (SizeOrder::Asc, Some(size)) => (0, *size as i128),
+        (SizeOrder::Desc, Some(size)) => (0, -(*size as i128)),
+      });
+      urls = sizes.into_iter().map(|(url, _)| url).collect();
+    }
+
+    // Spread the schedule across hosts
+    if self.interleave_hosts {
+      urls = utils::interleave_hosts(urls);
+    }
+    urls
+  }
+
+  /// Split `urls` into the URLs to download and `(original, duplicate)` pairs
+  /// of URLs on the same host serving the same strong ETag, resolved with
+  /// HEAD requests
+  async fn group_by_etag(
+    &self,
+    urls: Vec<String>,
+  ) -> (Vec<String>, Vec<(String, String)>) {
+    self.clone().with_urls(urls.clone()).presize().await;
+    let etags = self.etags.lock().await;
+    let mut originals = HashMap::new();
+    let mut unique = Vec::new();
+    let mut duplicates = Vec::new();
+    for url in urls {
+      let Some(etag) = etags.get(&url) else {
+        unique.push(url);
+        continue;
+      };
+      let host =
+        Url::parse(&url).map(|u| u.authority().to_string()).unwrap_or_default();
+      match originals.entry((host, etag.clone())) {
+        Entry::Occupied(e) => {
+          duplicates.push((String::clone(e.get()), url));
+        },
+        Entry::Vacant(e) => {
+          e.insert(url.clone());
+          unique.push(url);
+        },
+      }
+    }
+    if !duplicates.is_empty() {
+      info!(
+        "Skipping {} URLs whose ETag matches another URL",
+        duplicates.len()
+      );
+    }
+    (unique, duplicates)
+  }
+
+  /// Hardlink the download of `original` to the filename of `duplicate`,
+  /// copying it where hardlinks are not supported
+  fn link_duplicate(&self, original: &str, duplicate: &str) -> Result<()> {
+    let source = self.dest.join(self.local_filename(original));
+    let target = self.dest.join(self.local_filename(duplicate));
+    if target.exists() || source == target {
+      return Ok(());
+    }
+    info!("Linking {} to {}", target.display(), source.display());
+    if fs::hard_link(&source, &target).is_err() {
+      fs::copy(&source, &target)?;
+    }
+    Ok(())
+  }
+
+  /// Whether `url` or its filename is in the skip list
+  fn is_skip_listed(&self, url: &str) -> bool {
+    self.skip_list.contains(url)
+      || self.skip_list.contains(&self.local_filename(url))
+  }
+
+  /// Whether the filename of `url` passes the extension filters
+  fn matches_extensions(&self, url: &str) -> bool {
+    let filename = self.local_filename(url);
+    (self.only_extensions.is_empty()
+      || filename::has_extension(&filename, &self.only_extensions))
+      && !filename::has_extension(&filename, &self.exclude_extensions)
+  }
+
+  /// Whether a streamed `url` passes the skip list and extension filters,
+  /// the only parts of the schedule that apply to streamed URLs
+  fn is_scheduled(&self, url: &str) -> bool {
+    if self.is_skip_listed(url) {
+      info!("Skipping {}, listed in the skip list", url);
+      return false;
+    }
+    self.matches_extensions(url)
+  }
+
+  /// Plan a run without writing anything to disk, resolving sizes with HEAD
+  /// requests
+  pub async fn plan(&self) -> Plan {
+    let mut planner = self.clone();
+    planner.disambiguate_filenames();
+    let planner = &planner;
+    let urls = planner.scheduled_urls().await;
+    let entries = futures::stream::iter(urls)
+      .map(|url| async move {
+        let filename = planner.local_filename(&url);
+        let filepath = planner.dest.join(&filename);
+        // A clean run removes existing files first
+        let keep = !planner.clean
+          && filepath.exists()
+          && planner
+            .keep_existing(&url, &filepath)
+            .await
+            .map_or(true, |status| status.is_some());
+        let partial = partial_path(&filepath)
+          .metadata()
+          .map(|m| m.len())
+          .ok()
+          .filter(|&len| len > 0 && !planner.clean);
+        let (size, action, mut error) = if keep {
+          let size = filepath.metadata().map(|m| m.len()).ok();
+          (size, PlannedAction::Skip, None)
+        } else {
+          match planner.get_file_size(&url).await {
+            Ok(size) if partial.is_some_and(|partial| partial < size) => {
+              (Some(size), PlannedAction::Resume, None)
+            },
+            Ok(size) => (Some(size), PlannedAction::Download, None),
+            Err(e) => (None, PlannedAction::Unresolved, Some(e.to_string())),
+          }
+        };
+        let (redirects, final_url) = match &planner.redirect_client {
+          Some(client) => match planner.trace_redirects(client, &url).await {
+            Ok((redirects, final_url)) => (redirects, Some(final_url)),
+            Err(e) => {
+              error.get_or_insert_with(|| e.to_string());
+              (Vec::new(), None)
+            },
+          },
+          None => (Vec::new(), None),
+        };
+        PlanEntry {
+          url,
+          filename,
+          size,
+          partial: partial.filter(|_| action == PlannedAction::Resume),
+          action,
+          redirects,
+          final_url,
+          error,
+        }
+      })
+      .buffered(planner.workers.max(1))
+      .collect::<Vec<_>>()
+      .await;
+    planner.seen_urls.lock().await.clear();
+    *planner.total_size.lock().await = 0;
+    Plan::new(entries)
+  }
+
+  /// Follow the redirects of `url` one HEAD request at a time with `client`,
+  /// which must not follow them itself.
+  ///
+  /// Returns every `Location` visited and the URL the chain ends at,
+  /// credentials are only sent to the host of `url`
+  async fn trace_redirects(
+    &self,
+    client: &Client,
+    url: &str,
+  ) -> Result<(Vec<String>, String)> {
+    let origin = Url::parse(url)?;
+    let mut current = origin.clone();
+    let mut redirects = Vec::new();
+    while redirects.len() <= MAX_REDIRECTS {
+      let mut request = client.head(current.clone());
+      if current.host() == origin.host() {
+        request = self.authorize(request);
+      }
+      if let Some(timeout) = self.head_timeout {
+        request = request.timeout(timeout);
+      }
+      let resp = request.send().await?;
+      let location = resp
+        .headers()
+        .get(reqwest::header::LOCATION)
+        .and_then(|v| v.to_str().ok());
+      let Some(location) = location.filter(|_| resp.status().is_redirection())
+      else {
+        return Ok((redirects, current.to_string()));
+      };
+      current = current.join(location).map_err(|e| {
+        error::DownloadError::InvalidUrlError(format!("{}: {}", location, e))
+      })?;
+      redirects.push(current.to_string());
+    }
+    Err(error::DownloadError::TooManyRedirects(url.to_string()))
+  }
+
+  /// Download a single file at `url` and show progress bar in `mp` and updating
+  /// `total_pb`.
+  ///
+  /// Returns how the file was obtained on success
+  ///
+  /// Skips file if it already exists
+  /// Resumes download if file already exists and is partially downloaded
+  #[tracing::instrument(skip(self, mp, total_pb), fields(url), err(level = tracing::Level::ERROR))]
+  pub async fn download_file(
+    &self,
+    url: String,
+    mp: Arc<MultiProgress>,
+    total_pb: ProgressBar,
+  ) -> Result<DownloadOutcome> {
+    let mut filename = self.local_filename(&url);
+    let mut filepath = self.local_path(&url, &filename)?;
+    let mut temp_filepath = partial_path(&filepath);
+    // Pick up downloads left in the fallback directory, the final rename
+    // still lands in `self.dest`
+    if let Some(fallback) = &self.resume_from
+      && !filepath.exists()
+      && !temp_filepath.exists()
+    {
+      for path in [&filepath, &temp_filepath] {
+        let old = fallback.join(path.file_name().unwrap_or_default());
+        if old.is_file() {
+          info!("Moving {} to {}", old.display(), path.display());
+          utils::move_file(&old, path)?;
+          break;
+        }
+      }
+    }
+    // Discard stale partials, the remote file may have changed since
+    if let Some(ttl) = self.resume_metadata_ttl {
+      let age = temp_filepath
+        .metadata()
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|mtime| mtime.elapsed().ok());
+      if age.is_some_and(|age| age > ttl) {
+        info!(
+          "Discarding stale partial download older than {}: {}",
+          humantime::format_duration(ttl),
+          temp_filepath.display()
+        );
+        fs::remove_file(&temp_filepath)?;
+      }
+    }
+    // Resuming a tiny partial is not worth the ranged request
+    if let Some(threshold) = self.resume_partial_threshold
+      && let Ok(meta) = temp_filepath.metadata()
+      && meta.len() < threshold
+    {
+      fs::remove_file(&temp_filepath)?;
+    }
+    // Skip if file exists, unless configured to download it again
+    if filepath.exists()
+      && let Some(status) = self.keep_existing(&url, &filepath).await?
+    {
+      return self
+        .skip_existing(&url, &filename, &filepath, status, &mp, &total_pb)
+        .await;
+    }
+
+    // Get existing size for resume
+    let mut start_byte = temp_filepath.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut file_total_size = self.get_file_size(&url).await?;
+    // The HEAD response may name the file differently from its URL
+    let named = self.local_filename(&url);
+    if named != filename {
+      filename = named;
+      filepath = self.local_path(&url, &filename)?;
+      temp_filepath = partial_path(&filepath);
+      if filepath.exists()
+        && let Some(status) = self.keep_existing(&url, &filepath).await?
+      {
+        return self
+          .skip_existing(&url, &filename, &filepath, status, &mp, &total_pb)
+          .await;
+      }
+      start_byte = temp_filepath.metadata().map(|m| m.len()).unwrap_or(0);
+    }
+    // A partial larger than the remote file means the remote shrank since,
+    // the partial is stale
+    if self.resume_check_remote
+      && file_total_size > 0
+      && start_byte > file_total_size
+    {
+      info!(
+        "Discarding partial download larger than the remote file ({} > {}): {}",
+        start_byte,
+        file_total_size,
+        temp_filepath.display()
+      );
+      fs::remove_file(&temp_filepath)?;
+      start_byte = 0;
+    }
+    // Update total size message for total progress bar tracker
+    total_pb.set_message(human_readable_size(*self.total_size.lock().await));
+
+    // Setup progress bar
+    let pb = mp.add(ProgressBar::new(file_total_size));
+    pb.set_style(
+      ProgressStyle::default_bar()
+        .template(&progress::file_template())?
+        .progress_chars("+>-"),
+    );
+    pb.set_message(format!(
+      "\x1b[93m{}\x1b[0m  {}",
+      human_readable_size(file_total_size),
+      filename,
+    ));
+    self
+      .emit(ProgressEvent::Started {
+        url:    url.clone(),
+        total:  Some(file_total_size).filter(|&size| size > 0),
+        offset: start_byte,
+      })
+      .await;
+
+    // Check if Resume download done, which needs a known size: without one
+    // the ranged GET reveals it through `Content-Range`
+    if start_byte > 0 {
+      pb.set_position(start_byte);
+      if file_total_size > 0 && start_byte >= file_total_size {
+        let sha256 = self.sha256_of(&url, &temp_filepath, None).await?;
+        if let Err(e) =
+          self.verify_checksums(&url, &temp_filepath, sha256.as_deref()).await
+        {
+          pb.finish_and_clear();
+          return Err(e);
+        }
+        total_pb.inc(1); // Increment total progress for completed partials
+        self.promote(&temp_filepath, &filepath).unwrap_or(());
+        self.apply_file_mode(&filepath)?;
+        self.record_lock_entry(&url, None, None, &filepath, sha256).await?;
+        self.extract_archive(&filepath).await;
+        pb.set_position(start_byte);
+        pb.finish_with_message(format!(
+          "\x1b[96mDone\x1b[0m \x1b[92m{}\x1b[0m  {} {}",
+          human_readable_size(file_total_size),
+          filename,
+          "✔",
+        ));
+        tokio::time::sleep(tokio::time::Duration::from_millis(
+          rand::random_range(500..1000),
+        ))
+        .await;
+        pb.finish_and_clear();
+        return Ok(DownloadOutcome::Resumed);
+      }
+    }
+
+    // Fetch large files in concurrent ranges when enabled and supported,
+    // otherwise stream sequentially. Either way the SHA-256 is computed as
+    // the file is written, when needed.
+    let mut hasher =
+      self.needs_sha256(&url).then(|| Hasher::new(Algorithm::Sha256));
+    let (final_url, headers) = if let Some(ranges) = self
+      .split_ranges(&url, &temp_filepath, start_byte, file_total_size)
+      .await?
+    {
+      self
+        .download_segments(&url, &temp_filepath, &ranges, &pb, hasher.as_mut())
+        .await?
+    } else {
+      // Setup request with range header for resume
+      let mut request = self
+        .authorize(self.client.get(&url))
+        .header("Range", format!("bytes={}-", start_byte));
+      // Byte offsets of compressed transfers do not match the uncompressed
+      // partial, resume uncompressed
+      if start_byte > 0 {
+        request = request.header("Accept-Encoding", "identity");
+      }
+      let resp = match self.check_response(&url, request.send().await?).await {
+        Ok(Some(resp)) => resp,
+        // Start over after a 429, the partial may have changed meanwhile
+        Ok(None) => {
+          pb.finish_and_clear();
+          return Box::pin(self.download_file(url, mp, total_pb)).await;
+        },
+        Err(e) => {
+          pb.finish_and_clear();
+          return Err(e);
+        },
+      };
+
+      // Update total size if not already determined from HEAD, a ranged
+      // response only knows the full size from `Content-Range`
+      if file_total_size == 0 && resp.status().is_success() {
+        file_total_size = resp
+          .headers()
+          .get("content-range")
+          .and_then(|v| v.to_str().ok())
+          .and_then(parse_content_range_total)
+          .or_else(|| {
+            resp
+              .headers()
+              .get("content-length")
+              .and_then(|v| v.to_str().ok().and_then(|s| s.parse::<u64>().ok()))
+              .map(|len| {
+                if resp.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+                  start_byte + len
+                } else {
+                  len
+                }
+              })
+          })
+          .unwrap_or(0);
+        pb.set_length(file_total_size);
+        self.seen_urls.lock().await.insert(url.clone());
+        *self.total_size.lock().await += file_total_size;
+        total_pb
+          .set_message(human_readable_size(*self.total_size.lock().await));
+      }
+
+      self.record_disposition(&url, resp.headers());
+      let final_url = resp.url().to_string();
+      let headers = resp.headers().clone();
+
+      let mut file = self.open_partial(&temp_filepath, false)?;
+
+      // Stream chunks and write to file, re-chunking to `read_chunk_size` when
+      // set
+      let mut offset = file.metadata()?.len();
+      // A resumed download's digest covers the partial it continues
+      if offset > 0
+        && let Some(prefix) = hasher.take()
+      {
+        let path = temp_filepath.clone();
+        hasher = Some(
+          task::spawn_blocking(move || {
+            let mut prefix = prefix;
+            prefix.update_file(&path).map(|_| prefix)
+          })
+          .await
+          .map_err(std::io::Error::other)??,
+        );
+      }
+      let mut rate_share = self.rate_limiter.share();
+      let mut stream = resp.bytes_stream();
+      let mut buffer = Vec::with_capacity(self.read_chunk_size.unwrap_or(0));
+      let mut received = offset;
+      let mut last_chunk = std::time::Instant::now();
+      while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        received += chunk.len() as u64;
+        self.trace_chunk(&url, chunk.len(), received, &mut last_chunk);
+        rate_share.acquire(chunk.len() as u64).await;
+        if let Some(hasher) = &mut hasher {
+          hasher.update(&chunk);
+        }
+        let Some(chunk_size) = self.read_chunk_size else {
+          self.write_chunk(&url, &mut file, &mut offset, &chunk).await?;
+          pb.inc(chunk.len() as u64);
+          continue;
+        };
+        buffer.extend_from_slice(&chunk);
+        while buffer.len() >= chunk_size {
+          self
+            .write_chunk(&url, &mut file, &mut offset, &buffer[..chunk_size])
+            .await?;
+          pb.inc(chunk_size as u64);
+          buffer.drain(..chunk_size);
+        }
+      }
+      if !buffer.is_empty() {
+        self.write_chunk(&url, &mut file, &mut offset, &buffer).await?;
+        pb.inc(buffer.len() as u64);
+      }
+      drop(file);
+      drop(rate_share);
+      (final_url, headers)
+    };
+
+    // Never promote a short (or overlong) body to the final file. Short
+    // partials are kept for resuming, overlong ones cannot be trusted.
+    let written = temp_filepath.metadata()?.len();
+    if file_total_size > 0 && written != file_total_size {
+      pb.finish_and_clear();
+      if written > file_total_size {
+        fs::remove_file(&temp_filepath)?;
+      }
+      return Err(error::DownloadError::IncompleteDownload {
+        url,
+        expected: file_total_size,
+        actual: written,
+      });
+    }
+
+    let sha256 = self
+      .sha256_of(&url, &temp_filepath, hasher.map(Hasher::finalize))
+      .await?;
+    if let Err(e) =
+      self.verify_checksums(&url, &temp_filepath, sha256.as_deref()).await
+    {
+      pb.finish_and_clear();
+      return Err(e);
+    }
+
+    // Only the download response may have named the file
+    let named = self.local_filename(&url);
+    if named != filename {
+      filename = named;
+      filepath = self.local_path(&url, &filename)?;
+    }
+
+    // Rename temp file to final location
+    self.promote(&temp_filepath, &filepath)?;
+    self.apply_file_mode(&filepath)?;
+    let etag =
+      headers.get("etag").and_then(|v| v.to_str().ok()).map(str::to_string);
+    if self.etag_cache {
+      let sidecar = etag_sidecar(&filepath);
+      match &etag {
+        Some(etag) => fs::write(&sidecar, etag)?,
+        None if sidecar.exists() => fs::remove_file(&sidecar)?,
+        None => (),
+      }
+    }
+    self
+      .record_lock_entry(
+        &url,
+        Some(final_url.clone()),
+        etag,
+        &filepath,
+        sha256.clone(),
+      )
+      .await?;
+    self.extract_archive(&filepath).await;
+    if self.provenance {
+      let path = filepath.clone();
+      task::spawn_blocking(move || {
+        provenance::Provenance::new(&url, &final_url, &headers, &path, sha256)?
+          .write_sidecar(&path)
+      })
+      .await
+      .map_err(std::io::Error::other)??;
+    }
+    pb.finish_with_message(format!(
+      "\x1b[32mOk\x1b[0m \x1b[32m{}\x1b[0m  {} {}",
+      human_readable_size(file_total_size),
+      filename,
+      "✔",
+    ));
+    total_pb.inc(1); // Increment total progress when download completes
+    tokio::time::sleep(tokio::time::Duration::from_millis(rand::random_range(
+      500..1000,
+    )))
+    .await;
+    pb.finish_and_clear();
+
+    if start_byte > 0 {
+      Ok(DownloadOutcome::Resumed)
+    } else {
+      Ok(DownloadOutcome::Downloaded)
+    }
+  }
+
+  /// Byte ranges to fetch `url` in with `--split`, `None` to stream it
+  /// sequentially: when splitting is off, the server did not advertise range
+  /// support, the size is unknown or below `--split-threshold`, or a
+  /// sequential partial exists. Larger files get as many ranges as
+  /// `--split` asks for and `--min-chunk-size` allows.
+  ///
+  /// The ranges of an interrupted split download, recorded next to its
+  /// segments, are reused whatever `--split` is now, so only the missing
+  /// bytes are fetched again. Segments the remote file no longer matches in
+  /// size are discarded.
+  async fn split_ranges(
+    &self,
+    url: &str,
+    temp_filepath: &std::path::Path,
+    start_byte: u64,
+    total: u64,
+  ) -> Result<Option<Vec<(u64, u64)>>> {
+    let ranged = self.ranged_urls.lock().await.contains(url);
+    let sidecar = ranges_sidecar(temp_filepath);
+    if let Some(ranges) = read_ranges(&sidecar) {
+      if ranged
+        && start_byte == 0
+        && ranges.last().map(|r| r.1 + 1) == Some(total)
+      {
+        info!("Resuming split download of {} in {} ranges", url, ranges.len());
+        return Ok(Some(ranges));
+      }
+      info!("Discarding stale split download: {}", temp_filepath.display());
+      for i in 0..ranges.len() {
+        fs::remove_file(segment_path(temp_filepath, i)).or_else(|e| match e
+          .kind()
+        {
+          std::io::ErrorKind::NotFound => Ok(()),
+          _ => Err(e),
+        })?;
+      }
+      fs::remove_file(&sidecar)?;
+    }
+    let n = split_count(
+      self.split as u64,
+      total,
+      self.split_threshold,
+      self.min_chunk_size,
+    );
+    if n < 2 || start_byte > 0 || !ranged {
+      return Ok(None);
+    }
+    Ok(Some((0..n).map(|i| (i * total / n, (i + 1) * total / n - 1)).collect()))
+  }
+
+  /// Download `ranges` of `url` concurrently into `<temp_filepath>.N`
+  /// segments and concatenate them into `temp_filepath`.
+  ///
+  /// The ranges are recorded in `<temp_filepath>.ranges` until the segments
+  /// are joined, the length of each segment is how much of its range is
+  /// done, so segments left by an interrupted run are resumed. The segments
+  /// share one [`RateShare`] so a split download gets the same bandwidth as
+  /// any other. `hasher` is fed the file as the segments are joined.
+  /// Returns the final URL and headers of the first segment response.
+  async fn download_segments(
+    &self,
+    url: &str,
+    temp_filepath: &std::path::Path,
+    ranges: &[(u64, u64)],
+    pb: &ProgressBar,
+    mut hasher: Option<&mut Hasher>,
+  ) -> Result<(String, reqwest::header::HeaderMap)> {
+    let segment_path = |i: usize| segment_path(temp_filepath, i);
+    let sidecar = ranges_sidecar(temp_filepath);
+    if !sidecar.exists() {
+      write_ranges(&sidecar, ranges)?;
+    }
+    let rate_share = tokio::sync::Mutex::new(self.rate_limiter.share());
+    let responses = futures::future::try_join_all(
+      ranges.iter().enumerate().map(|(i, &(start, end))| {
+        self.download_segment(
+          url,
+          segment_path(i),
+          (start, end),
+          &rate_share,
+          pb.clone(),
+        )
+      }),
+    )
+    .await?;
+    drop(rate_share);
+
+    let mut file = self.open_partial(temp_filepath, true)?;
+    let mut buf = vec![0; 64 * 1024];
+    for i in 0..ranges.len() {
+      let mut segment = File::open(segment_path(i))?;
+      loop {
+        let n = std::io::Read::read(&mut segment, &mut buf)?;
+        if n == 0 {
+          break;
+        }
+        file.write_all(&buf[..n])?;
+        if let Some(hasher) = hasher.as_deref_mut() {
+          hasher.update(&buf[..n]);
+        }
+      }
+    }
+    drop(file);
+    for i in 0..ranges.len() {
+      fs::remove_file(segment_path(i))?;
+    }
+    fs::remove_file(&sidecar)?;
+
+    Ok(
+      responses
+        .into_iter()
+        .flatten()
+        .next()
+        .unwrap_or_else(|| (url.to_string(), Default::default())),
+    )
+  }
+
+  /// Download bytes `start..=end` of `url` into `path`, resuming what is
+  /// already there and throttled by `rate_share`. Returns the final URL and
+  /// headers of the response, if a request was needed.
+  async fn download_segment(
+    &self,
+    url: &str,
+    path: PathBuf,
+    (start, end): (u64, u64),
+    rate_share: &tokio::sync::Mutex<RateShare>,
+    pb: ProgressBar,
+  ) -> Result<Option<(String, reqwest::header::HeaderMap)>> {
+    let mut file = self.open_partial(&path, false)?;
+    let mut offset = file.metadata()?.len();
+    pb.inc(offset);
+    if offset > 0 {
+      self
+        .emit(ProgressEvent::Progress {
+          url:   url.to_string(),
+          bytes: offset,
+        })
+        .await;
+    }
+    if start + offset > end {
+      return Ok(None);
+    }
+    let resp = loop {
+      let resp = self
+        .authorize(self.client.get(url))
+        .header("Range", format!("bytes={}-{}", start + offset, end))
+        .header("Accept-Encoding", "identity")
+        .send()
+        .await?;
+      if let Some(resp) = self.check_response(url, resp).await? {
+        break resp;
+      }
+    };
+    if resp.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+      return Err(error::DownloadError::RangeNotSupported(url.to_string()));
+    }
+    let response = (resp.url().to_string(), resp.headers().clone());
+    let mut stream = resp.bytes_stream();
+    let mut last_chunk = std::time::Instant::now();
+    while let Some(chunk) = stream.next().await {
+      let chunk = chunk?;
+      let position = start + offset + chunk.len() as u64;
+      self.trace_chunk(url, chunk.len(), position, &mut last_chunk);
+      rate_share.lock().await.acquire(chunk.len() as u64).await;
+      self.write_chunk(url, &mut file, &mut offset, &chunk).await?;
+      pb.inc(chunk.len() as u64);
+    }
+    Ok(Some(response))
+  }
+
+  /// Log a chunk of `len` bytes of `url` received up to byte `position` with
+  /// `--trace-chunks`, with the time since the `last` chunk so stalls stand
+  /// out
+  fn trace_chunk(
+    &self,
+    url: &str,
+    len: usize,
+    position: u64,
+    last: &mut std::time::Instant,
+  ) {
+    if self.trace_chunks {
+      tracing::trace!(
+        "Chunk of {} bytes from {} up to byte {}, {:?} after the previous one",
+        len,
+        url,
+        position,
+        last.elapsed()
+      );
+      *last = std::time::Instant::now();
+    }
+  }
+
+  /// Extract `path` if `--extract` is set and it is an archive.
+  ///
+  /// Failures are logged and counted in the run summary but do not fail the
+  /// download.
+  async fn extract_archive(&self, path: &std::path::Path) {
+    if !self.extract {
+      return;
+    }
+    let Some(kind) = extract::ArchiveKind::detect(path) else {
+      return;
+    };
+    let Ok(_permit) = self.extract_permits.acquire().await else {
+      return;
+    };
+    let archive = path.to_path_buf();
+    let res = task::spawn_blocking(move || extract::extract(&archive, kind))
+      .await
+      .map_err(std::io::Error::other)
+      .and_then(|res| res);
+    match res {
+      Ok(dir) => info!("Extracted {} to {}", path.display(), dir.display()),
+      Err(e) => {
+        tracing::error!("Failed to extract {}: {}", path.display(), e);
+        self
+          .extraction_failures
+          .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+      },
+    }
+  }
+
+  /// Whether the download of `url` needs its SHA-256, to verify it or for
+  /// the manifest lock
+  fn needs_sha256(&self, url: &str) -> bool {
+    self.remote_checksum_suffix.is_some()
+      || self.manifest_lock.is_some()
+      || self.expected_checksum(url).is_some()
+  }
+
+  /// SHA-256 of the download of `url` at `path` if [`Self::needs_sha256`]:
+  /// `streamed` when it was computed while downloading, otherwise read back
+  /// from `path`
+  async fn sha256_of(
+    &self,
+    url: &str,
+    path: &std::path::Path,
+    streamed: Option<String>,
+  ) -> Result<Option<String>> {
+    if !self.needs_sha256(url) {
+      return Ok(None);
+    }
+    match streamed {
+      Some(sha256) => Ok(Some(sha256)),
+      None => digest_file(path, Algorithm::Sha256).await.map(Some),
+    }
+  }
+
+  /// Verify the download of `url` at `path`, whose SHA-256 is `sha256`,
+  /// against all configured checksums
+  async fn verify_checksums(
+    &self,
+    url: &str,
+    path: &std::path::Path,
+    sha256: Option<&str>,
+  ) -> Result<()> {
+    let Some(sha256) = sha256 else {
+      return Ok(());
+    };
+    self.verify_remote_checksum(url, path, sha256).await?;
+    self.verify_expected_checksum(url, path, sha256)
+  }
+
+  /// Verify the download of `url` at `path` against the digest published at
+  /// `<url><suffix>`, if enabled.
+  ///
+  /// A missing checksum file skips verification with a warning, a mismatch
+  /// deletes `path` and fails.
+  async fn verify_remote_checksum(
+    &self,
+    url: &str,
+    path: &std::path::Path,
+    sha256: &str,
+  ) -> Result<()> {
+    let Some(suffix) = &self.remote_checksum_suffix else {
+      return Ok(());
+    };
+    let checksum_url = format!("{}{}", url, suffix);
+    let resp = self.authorize(self.client.get(&checksum_url)).send().await?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+      tracing::warn!("No checksum at {}, not verifying {}", checksum_url, url);
+      return Ok(());
+    }
+    let text = resp.error_for_status()?.text().await?;
+    let Some(expected) =
+      checksum::parse_checksum_file(&text, &Self::get_filename(url))
+    else {
+      tracing::warn!(
+        "No SHA-256 digest in {}, not verifying {}",
+        checksum_url,
+        url
+      );
+      return Ok(());
+    };
+    Self::check_sha256(url, path, &expected, sha256)
+  }
+
+  /// Verify the download of `url` at `path` against the digest given for its
+  /// URL or local filename with `--checksum-file`, if any.
+  ///
+  /// A mismatch deletes `path` and fails.
+  fn verify_expected_checksum(
+    &self,
+    url: &str,
+    path: &std::path::Path,
+    sha256: &str,
+  ) -> Result<()> {
+    match self.expected_checksum(url) {
+      Some(expected) => Self::check_sha256(url, path, expected, sha256),
+      None => Ok(()),
+    }
+  }
+
+  /// Digest given for the URL or local filename of `url` with
+  /// `--checksum-file`
+  fn expected_checksum(&self, url: &str) -> Option<&String> {
+    self
+      .expected_checksums
+      .get(url)
+      .or_else(|| self.expected_checksums.get(&self.local_filename(url)))
+      .or_else(|| self.expected_checksums.get(&Self::get_filename(url)))
+  }
+
+  /// Compare the SHA-256 `actual` of `path` with `expected`, deleting `path`
+  /// and failing on a mismatch
+  fn check_sha256(
+    url: &str,
+    path: &std::path::Path,
+    expected: &str,
+    actual: &str,
+  ) -> Result<()> {
+    if actual != expected {
+      fs::remove_file(path)?;
+      return Err(error::DownloadError::ChecksumMismatch {
+        url:      url.to_string(),
+        expected: expected.to_string(),
+        actual:   actual.to_string(),
+      });
+    }
+    info!("Verified SHA-256 of {}", url);
+    Ok(())
+  }
+
+  /// Append `buf`, downloaded from `url`, to `file`, which is `offset` bytes
+  /// long, retrying transient disk errors up to `disk_retries` times.
+  ///
+  /// Waits for a write permit first with `--max-concurrent-writes`, the
+  /// download keeps buffering in the meantime.
+  async fn write_chunk(
+    &self,
+    url: &str,
+    file: &mut File,
+    offset: &mut u64,
+    buf: &[u8],
+  ) -> Result<()> {
+    let _permit = match &self.write_permits {
+      Some(permits) => Some(permits.acquire().await.unwrap()),
+      None => None,
+    };
+    let mut attempt = 0;
+    loop {
+      match file.write_all(buf) {
+        Ok(()) => {
+          *offset += buf.len() as u64;
+          self.counters.bytes.fetch_add(buf.len() as u64, Ordering::Relaxed);
+          self.touch();
+          if self.events.is_some() {
+            self
+              .emit(ProgressEvent::Progress {
+                url:   url.to_string(),
+                bytes: buf.len() as u64,
+              })
+              .await;
+          }
+          return Ok(());
+        },
+        Err(e)
+          if attempt < self.disk_retries
+            && utils::is_transient_io_error(&e) =>
+        {
+          attempt += 1;
+          tracing::warn!(
+            "Disk write failed ({}), retrying {}/{}",
+            e,
+            attempt,
+            self.disk_retries
+          );
+          // Drop whatever part of `buf` was written before retrying
+          file.set_len(*offset)?;
+          tokio::time::sleep(tokio::time::Duration::from_millis(
+            500 * attempt as u64,
+          ))
+          .await;
+        },
+        Err(e) => return Err(e.into()),
+      }
+    }
+  }
+
+  /// Send `event` to the channel of [`Downloader::run_with_events`], if any
+  async fn emit(&self, event: ProgressEvent) {
+    if let Some(events) = &self.events {
+      // A dropped receiver stops the events, not the run
+      events.send(event).await.ok();
+    }
+  }
+
+  /// Record that the run made progress, for `max_idle_time`
+  fn touch(&self) {
+    *self.last_activity.lock().unwrap() = std::time::Instant::now();
+  }
+
+  /// Resolve once the run has made no progress for `max_idle`
+  async fn idle_watchdog(&self, max_idle: std::time::Duration) {
+    let tick = (max_idle / 4).clamp(
+      std::time::Duration::from_millis(100),
+      std::time::Duration::from_secs(1),
+    );
+    loop {
+      tokio::time::sleep(tick).await;
+      if self.last_activity.lock().unwrap().elapsed() > max_idle {
+        return;
+      }
+    }
+  }
+
+  /// Add the configured credentials, if any, to `request`
+  fn authorize(
+    &self,
+    request: reqwest::RequestBuilder,
+  ) -> reqwest::RequestBuilder {
+    match &self.auth {
+      Some(auth) => auth.apply(request),
+      None => request,
+    }
+  }
+
+  /// Fail with [`error::DownloadError::ResponseHeadersTooLarge`] when the
+  /// headers of `resp`, each counted as `name: value\r\n`, add up to more
+  /// than `--max-response-header-bytes`
+  fn check_header_size(
+    &self,
+    url: &str,
+    resp: &reqwest::Response,
+  ) -> Result<()> {
+    let Some(limit) = self.max_response_header_bytes else {
+      return Ok(());
+    };
+    let size = resp
+      .headers()
+      .iter()
+      .map(|(name, value)| (name.as_str().len() + value.len() + 4) as u64)
+      .sum();
+    if size > limit {
+      return Err(error::DownloadError::ResponseHeadersTooLarge {
+        url: url.to_string(),
+        size,
+        limit,
+      });
+    }
+    Ok(())
+  }
+
+  /// Remember the status and headers of `resp` as the last response to `url`
+  /// with `--include-headers-in-report`
+  fn record_response(&self, url: &str, resp: &reqwest::Response) {
+    if self.include_headers {
+      self.responses.lock().unwrap().insert(
+        url.to_string(),
+        ResponseRecord::new(resp.status().as_u16(), resp.headers()),
+      );
+    }
+  }
+
+  /// Remember the filename `url` is served as from the `Content-Disposition`
+  /// header of the first response to it naming one, normalized like derived
+  /// names and made unique against the names other URLs use
+  fn record_disposition(
+    &self,
+    url: &str,
+    headers: &reqwest::header::HeaderMap,
+  ) {
+    let Some(name) = headers
+      .get("content-disposition")
+      .and_then(|v| v.to_str().ok())
+      .and_then(filename::from_content_disposition)
+    else {
+      return;
+    };
+    let mut names = self.disposition_names.lock().unwrap();
+    if names.contains_key(url) {
+      return;
+    }
+    let name = self.filename_opts.nest(url, self.filename_opts.apply(name));
+    let mut claimed = self.claimed_names.lock().unwrap();
+    let name = match claimed.get(&name) {
+      Some(owner) if owner != url => {
+        let unique = numbered(&name, |n| claimed.contains_key(n));
+        info!("Saving {} as {}, {} is taken by another URL", url, unique, name);
+        unique
+      },
+      _ => name,
+    };
+    claimed.insert(name.clone(), url.to_string());
+    names.insert(url.to_string(), name);
+  }
+
+  /// Rename the completed partial at `temp` to `path`, syncing the data and
+  /// then the rename to disk first with `--fsync`
+  fn promote(
+    &self,
+    temp: &std::path::Path,
+    path: &std::path::Path,
+  ) -> std::io::Result<()> {
+    if self.fsync {
+      File::options().write(true).open(temp)?.sync_all()?;
+    }
+    fs::rename(temp, path)?;
+    // Directories cannot be opened as files on Windows, NTFS journals the
+    // rename itself
+    #[cfg(unix)]
+    if self.fsync
+      && let Some(dir) = path.parent()
+    {
+      File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+  }
+
+  /// Whether the existing `filepath` of `url` is kept rather than downloaded
+  /// again, following `--overwrite` and `--if-newer`.
+  ///
+  /// Returns the status to report kept files with.
+  async fn keep_existing(
+    &self,
+    url: &str,
+    filepath: &std::path::Path,
+  ) -> Result<Option<&'static str>> {
+    match self.existing {
+      ExistingPolicy::Skip => Ok(Some("Exists")),
+      ExistingPolicy::Overwrite => Ok(None),
+      ExistingPolicy::IfNewer => {
+        let modified = filepath.metadata()?.modified()?;
+        let mut request = self
+          .authorize(self.client.head(url))
+          .header("If-Modified-Since", httpdate::fmt_http_date(modified));
+        if self.etag_cache
+          && let Ok(etag) = fs::read_to_string(etag_sidecar(filepath))
+        {
+          request = request.header("If-None-Match", etag.trim());
+        }
+        let resp = request.send().await?;
+        if resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+          return Ok(Some("Not Modified"));
+        }
+        resp.error_for_status()?;
+        info!("Remote file changed, downloading again: {}", url);
+        Ok(None)
+      },
+    }
+  }
+
+  /// Report the download of `url` as skipped with `status` because
+  /// `filepath` exists
+  async fn skip_existing(
+    &self,
+    url: &str,
+    filename: &str,
+    filepath: &std::path::Path,
+    status: &str,
+    mp: &MultiProgress,
+    total_pb: &ProgressBar,
+  ) -> Result<DownloadOutcome> {
+    let pb = mp.add(ProgressBar::new(0));
+    pb.finish_with_message(format!(
+      "\x1b[93m{}\x1b[0m {} {}",
+      status, filename, "✔"
+    ));
+    self.record_lock_entry(url, None, None, filepath, None).await?;
+    total_pb.inc(1); // Increment total progress for skipped files
+    tokio::time::sleep(tokio::time::Duration::from_millis(rand::random_range(
+      200..500,
+    )))
+    .await;
+    pb.finish_and_clear();
+    Ok(DownloadOutcome::Skipped)
+  }
+
+  /// Count a 429 response to `url` and emit a structured event with its
+  /// host, `Retry-After` value and attempt number
+  async fn record_throttle(&self, url: &str, resp: &reqwest::Response) {
+    self.retries.record(RetryReason::RateLimited);
+    let attempt = {
+      let mut throttled = self.throttled.lock().await;
+      let count = throttled.entry(url.to_string()).or_default();
+      *count += 1;
+      *count
+    };
+    let host =
+      Url::parse(url).map(|u| u.authority().to_string()).unwrap_or_default();
+    let retry_after =
+      resp.headers().get("retry-after").and_then(|v| v.to_str().ok());
+    tracing::warn!(
+      host,
+      retry_after,
+      attempt,
+      "Rate limited (429) by {}",
+      host
+    );
+  }
+
+  /// Record what `url` resolved to for the manifest lock, if enabled, with
+  /// the `sha256` of `path` when already known
+  async fn record_lock_entry(
+    &self,
+    url: &str,
+    final_url: Option<String>,
+    etag: Option<String>,
+    path: &std::path::Path,
+    sha256: Option<String>,
+  ) -> Result<()> {
+    if self.manifest_lock.is_none() {
+      return Ok(());
+    }
+    let size = path.metadata()?.len();
+    let sha256 = match sha256 {
+      Some(sha256) => sha256,
+      None => digest_file(path, Algorithm::Sha256).await?,
+    };
+    self.lock_entries.lock().await.insert(
+      url.to_string(),
+      LockEntry {
+        final_url,
+        etag,
+        size,
+        sha256,
+      },
+    );
+    Ok(())
+  }
+
+  /// Write the manifest lock, or verify the run against it when frozen.
+  ///
+  /// The lock is only written when every URL was downloaded, so a partial run
+  /// keeps the previous lock file.
+  async fn finish_manifest_lock(
+    &self,
+    lock: &ManifestLock,
+    summary: &RunSummary,
+  ) -> Result<()> {
+    let actual = Lockfile::new(self.lock_entries.lock().await.clone());
+    if !lock.frozen {
+      if summary.failed + summary.skipped > 0 {
+        tracing::warn!(
+          "{} downloads failed or were skipped, not writing lock file {}",
+          summary.failed + summary.skipped,
+          lock.path.display()
+        );
+        return Ok(());
+      }
+      actual.save(&lock.path)?;
+      info!("Wrote lock file {}", lock.path.display());
+      return Ok(());
+    }
+    let drift = Lockfile::load(&lock.path)?.drift(&actual);
+    if !drift.is_empty() {
+      return Err(error::DownloadError::LockMismatch(drift.join("\n")));
+    }
+    info!("All downloads match lock file {}", lock.path.display());
+    Ok(())
+  }
+
+  /// Check a download response for `url` before streaming its body.
+  ///
+  /// Oversized headers, expired presigned URLs and other HTTP errors fail. A
+  /// 429 is counted and waited out as its `Retry-After` asks, returning `None`
+  /// so the caller sends the request again.
+  async fn check_response(
+    &self,
+    url: &str,
+    resp: reqwest::Response,
+  ) -> Result<Option<reqwest::Response>> {
+    self.record_response(url, &resp);
+    self.check_header_size(url, &resp)?;
+    if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+      self.record_throttle(url, &resp).await;
+      let retry_after = resp
+        .headers()
+        .get("retry-after")
+        .and_then(|v| v.to_str().ok())
+        .and_then(utils::parse_retry_after)
+        .unwrap_or_else(|| {
+          std::time::Duration::from_millis(rand::random_range(1000..3000))
+        });
+      tokio::time::sleep(retry_after).await;
+      return Ok(None);
+    }
+    // Expired presigned URLs answer 403 with a recognizable error body
+    if resp.status() == reqwest::StatusCode::FORBIDDEN {
+      let err = resp.error_for_status_ref().unwrap_err();
+      let body = resp.text().await.unwrap_or_default();
+      if is_presigned_url_expired(&body) {
+        return Err(error::DownloadError::PresignedUrlExpired(url.to_string()));
+      }
+      return Err(error::DownloadError::ReqwestError(err));
+    }
+    Ok(Some(resp.error_for_status()?))
+  }
+
+  /// Open the partial download at `path` for appending, or rewriting with
+  /// `truncate`, creating it with `self.file_mode`
+  fn open_partial(
+    &self,
+    path: &std::path::Path,
+    truncate: bool,
+  ) -> Result<File> {
+    let mut options = File::options();
+    if truncate {
+      options.create(true).write(true).truncate(true);
+    } else {
+      options.create(true).append(true);
+    }
+    #[cfg(unix)]
+    if let Some(mode) = self.file_mode {
+      use std::os::unix::fs::OpenOptionsExt;
+      options.mode(mode);
+    }
+    let file = options.open(path)?;
+    // The mode above is masked by the umask and ignored for existing files
+    self.apply_file_mode(path)?;
+    Ok(file)
+  }
+
+  /// Set the permissions of `path` to `self.file_mode`, if configured
+  fn apply_file_mode(&self, path: &std::path::Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = self.file_mode {
+      use std::os::unix::fs::PermissionsExt;
+      fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+  }
+
+  /// Run `self.refresh_cmd` to obtain a fresh URL for an expired presigned
+  /// `url`.
+  ///
+  /// The expired URL is passed as `$1` and in the `MFD_URL` environment
+  /// variable; the first non-empty line printed on stdout is the new URL.
+  #[tracing::instrument(skip(self), err(level = tracing::Level::ERROR))]
+  async fn refresh_url(&self, url: &str) -> Result<String> {
+    let cmd = self.refresh_cmd.as_deref().ok_or_else(|| {
+      error::DownloadError::RefreshCommandError("no --refresh-cmd".into())
+    })?;
+    let output = tokio::process::Command::new("sh")
+      .arg("-c")
+      .arg(cmd)
+      .arg("sh")
+      .arg(url)
+      .env("MFD_URL", url)
+      .output()
+      .await?;
+    if !output.status.success() {
+      return Err(error::DownloadError::RefreshCommandError(format!(
+        "`{}` exited with {}",
+        cmd, output.status
+      )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fresh =
+      stdout.lines().map(str::trim).find(|l| !l.is_empty()).ok_or_else(
+        || {
+          error::DownloadError::RefreshCommandError(format!(
+            "`{}` printed no URL",
+            cmd
+          ))
+        },
+      )?;
+    Ok(Url::parse(fresh)?.to_string())
+  }
+
+  /// Log active workers, throughput, files completed and remaining and the
+  /// ETA every `interval`, until aborted
+  async fn log_health(
+    &self,
+    interval: std::time::Duration,
+    total_pb: ProgressBar,
+  ) {
+    let mut last = self.counters.bytes.load(Ordering::Relaxed);
+    loop {
+      tokio::time::sleep(interval).await;
+      let bytes = self.counters.bytes.load(Ordering::Relaxed);
+      let rate = (bytes - last) as f64 / interval.as_secs_f64();
+      last = bytes;
+      let completed = self.counters.completed.load(Ordering::Relaxed) as u64;
+      let total = total_pb.length().unwrap_or(0);
+      let total_bytes = *self.total_size.lock().await;
+      let eta = if rate > 0.0 && total_bytes > bytes {
+        let secs = ((total_bytes - bytes) as f64 / rate) as u64;
+        humantime::format_duration(std::time::Duration::from_secs(secs))
+          .to_string()
+      } else {
+        "unknown".to_string()
+      };
+      info!(
+        "{} active workers, {}/s, {} of {} files completed, {} remaining, \
+         ETA {}",
+        self.counters.active.load(Ordering::Relaxed),
+        human_readable_size(rate as u64),
+        completed,
+        total,
+        total.saturating_sub(completed),
+        eta
+      );
+    }
+  }
+
+  /// Make `self.dest` the output file of the single URL, downloading it to
+  /// the parent directory
+  fn use_dest_as_file(&mut self) -> Result<()> {
+    let dest_error = |reason: &str| {
+      error::DownloadError::DestDirError(format!(
+        "{} {}",
+        self.dest.display(),
+        reason
+      ))
+    };
+    if self.dest.is_dir() {
+      return Err(dest_error("is a directory, not a file"));
+    }
+    let [url] = self.urls.as_slice() else {
+      return Err(dest_error("can only be the output file of a single URL"));
+    };
+    if self.url_stream.is_some() {
+      return Err(dest_error("can only be the output file of a single URL"));
+    }
+    let Some(name) = self.dest.file_name() else {
+      return Err(dest_error("does not name a file"));
+    };
+    self.output_names.insert(url.clone(), name.to_string_lossy().to_string());
+    self.dest = self
+      .dest
+      .parent()
+      .filter(|p| !p.as_os_str().is_empty())
+      .map(PathBuf::from)
+      .unwrap_or_else(|| PathBuf::from("."));
+    // Cleaning would wipe the parent directory
+    self.clean = false;
+    Ok(())
+  }
+
+  /// Give URLs deriving the same filename as an earlier one, or as a
+  /// filename given explicitly, a unique one by appending a counter, so they
+  /// neither overwrite nor skip each other.
+  ///
+  /// The names are claimed for their URLs, so names from `Content-Disposition`
+  /// headers seen later are made unique against them too.
+  fn disambiguate_filenames(&mut self) {
+    let mut taken = self
+      .output_names
+      .keys()
+      .map(|url| self.local_filename(url))
+      .collect::<HashSet<_>>();
+    for url in &self.urls {
+      if self.output_names.contains_key(url) {
+        continue;
+      }
+      let name = self.local_filename(url);
+      if taken.insert(name.clone()) {
+        continue;
+      }
+      let unique = numbered(&name, |n| taken.contains(n));
+      info!("Saving {} as {}, {} is taken by another URL", url, unique, name);
+      taken.insert(unique.clone());
+      self.unique_names.insert(url.clone(), unique);
+    }
+    let claimed = self
+      .urls
+      .iter()
+      .chain(self.output_names.keys())
+      .map(|url| (self.local_filename(url), url.clone()))
+      .collect();
+    *self.claimed_names.lock().unwrap() = claimed;
+  }
+
+  /// Semaphore limiting the concurrent downloads from the host of `url`,
+  /// `None` without a per-host limit
+  fn host_semaphore(&self, url: &str) -> Option<Arc<Semaphore>> {
+    let limit = self.per_host?;
+    let host = Url::parse(url)
+      .ok()
+      .and_then(|u| u.host_str().map(str::to_string))
+      .unwrap_or_default();
+    let mut permits = self.host_permits.lock().unwrap();
+    let semaphore =
+      permits.entry(host).or_insert_with(|| Arc::new(Semaphore::new(limit)));
+    Some(semaphore.clone())
+  }
+
+  /// Download `url` once a worker permit from `semaphore`, and a permit of
+  /// its host with a per-host limit, is available, recording its result and
+  /// notifying the webhook and the completion callback
+  async fn process_url(
+    self: Arc<Self>,
+    url: String,
+    mp: Arc<MultiProgress>,
+    total_pb: ProgressBar,
+    semaphore: Arc<Semaphore>,
+  ) -> (DownloadResult, Result<DownloadOutcome>) {
+    // Wait for the host first, so URLs queued behind a busy host do not hold
+    // worker permits other hosts could use
+    let host_permit = match self.host_semaphore(&url) {
+      Some(semaphore) => Some(semaphore.acquire_owned().await.unwrap()),
+      None => None,
+    };
+    let permit = semaphore.acquire().await.unwrap();
+    let started = std::time::Instant::now();
+    self.counters.active.fetch_add(1, Ordering::Relaxed);
+    let res = self.download_with_retries(&url, &mp, &total_pb).await;
+    self.counters.active.fetch_sub(1, Ordering::Relaxed);
+    self.counters.completed.fetch_add(1, Ordering::Relaxed);
+    self.touch();
+    // Let the next download start while the result is reported
+    drop(permit);
+    drop(host_permit);
+    let result = self.download_result(&url, &res, started.elapsed());
+    if let Some(log) = &self.results_log {
+      log.append(&result);
+    }
+    self
+      .emit(match &res {
+        Ok(outcome) => ProgressEvent::Finished {
+          url:     url.clone(),
+          path:    self.dest.join(&result.filename),
+          outcome: *outcome,
+        },
+        Err(e) => ProgressEvent::Error {
+          url:   url.clone(),
+          error: e.to_string(),
+        },
+      })
+      .await;
+    if let Some(on_complete) = &self.on_complete {
+      on_complete(&DownloadEvent {
+        url:    url.clone(),
+        path:   self.dest.join(&result.filename),
+        bytes:  result.bytes,
+        status: result.status,
+        error:  result.error.clone(),
+      });
+    }
+    if let Some(webhook) = &self.webhook {
+      let event = WebhookEvent::File {
+        filename: self.local_filename(&url),
+        url,
+        success: res.is_ok(),
+        error: res.as_ref().err().map(|e| e.to_string()),
+      };
+      webhook.send(&event).await;
+    }
+    (result, res)
+  }
+
+  /// Per-URL record of the download of `url` ending with `res`
+  fn download_result(
+    &self,
+    url: &str,
+    res: &Result<DownloadOutcome>,
+    elapsed: std::time::Duration,
+  ) -> DownloadResult {
+    let filename = self.local_filename(url);
+    let status = match res {
+      Ok(outcome) => *outcome,
+      Err(error::DownloadError::HostUnavailable(_)) => DownloadOutcome::Skipped,
+      Err(_) => DownloadOutcome::Failed,
+    };
+    DownloadResult {
+      url: url.to_string(),
+      bytes: res
+        .as_ref()
+        .ok()
+        .and_then(|_| self.dest.join(&filename).metadata().ok())
+        .map(|m| m.len()),
+      filename,
+      status,
+      elapsed_secs: elapsed.as_secs_f64(),
+      error: res.as_ref().err().map(|e| e.to_string()),
+      response: self.responses.lock().unwrap().get(url).cloned(),
+    }
+  }
+
+  /// Backoff before retry number `attempt`: `--retry-backoff-ms` doubled for
+  /// every earlier attempt, capped at a minute, plus up to half of it as
+  /// jitter
+  fn retry_delay(&self, attempt: u32) -> std::time::Duration {
+    let base = self
+      .retry_backoff
+      .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+      .min(std::time::Duration::from_secs(60));
+    base + base.mul_f64(rand::random_range(0.0..0.5))
+  }
+
+  /// Download `url`, refreshing expired presigned URLs, retrying transient
+  /// failures and tracking the health of its host
+  async fn download_with_retries(
+    &self,
+    url: &str,
+    mp: &Arc<MultiProgress>,
+    total_pb: &ProgressBar,
+  ) -> Result<DownloadOutcome> {
+    let mut url = url.to_string();
+    let host =
+      Url::parse(&url).map(|u| u.authority().to_string()).unwrap_or_default();
+    if let Some(breaker) = &self.circuit_breaker
+      && !breaker.allow(&host).await
+    {
+      info!("Skipping {} (host down)", url);
+      return Err(error::DownloadError::HostUnavailable(host));
+    }
+    let mut res =
+      self.download_file(url.clone(), mp.clone(), total_pb.clone()).await;
+    if let Err(error::DownloadError::PresignedUrlExpired(_)) = &res
+      && self.refresh_cmd.is_some()
+    {
+      info!("Presigned URL expired, refreshing: {}", url);
+      // Retries below keep using the fresh URL, the expired one would only
+      // fail again
+      res = match self.refresh_url(&url).await {
+        Ok(fresh) => {
+          url = fresh;
+          self.download_file(url.clone(), mp.clone(), total_pb.clone()).await
+        },
+        Err(e) => Err(e),
+      };
+    }
+    // Retry transient failures with an exponential backoff, resuming from
+    // the partial. Timeouts and checksum mismatches have their own limits,
+    // the bad file of a mismatch is already gone so the retry starts over.
+    let (mut attempts, mut timeouts, mut mismatches) = (0, 0, 0);
+    while let Err(e) = &res
+      && let Some(reason) = e.retry_reason()
+    {
+      let (attempt, limit) = match e {
+        error::DownloadError::Timeout(_) => {
+          (&mut timeouts, self.timeout_retries)
+        },
+        error::DownloadError::ChecksumMismatch {
+          ..
+        } => (&mut mismatches, self.checksum_retries),
+        _ => (&mut attempts, self.max_retries),
+      };
+      if *attempt >= limit {
+        break;
+      }
+      *attempt += 1;
+      let attempt = *attempt;
+      self.retries.record(reason);
+      let delay = self.retry_delay(attempt);
+      info!("Retrying {} in {:?} ({}/{}): {}", url, delay, attempt, limit, e);
+      tokio::time::sleep(delay).await;
+      res = self.download_file(url.clone(), mp.clone(), total_pb.clone()).await;
+    }
+    if let Some(breaker) = &self.circuit_breaker {
+      match &res {
+        Ok(_) => breaker.record_success(&host).await,
+        Err(e) if e.is_host_failure() => breaker.record_failure(&host).await,
+        Err(_) => breaker.record_neutral(&host).await,
+      }
+    }
+    res.inspect_err(|e| {
+      tracing::error!("Error downloading file from: {} error: {:?}", url, e)
+    })
+  }
+
+  /// Run like [`Downloader::run`], sending [`ProgressEvent`]s to `events`
+  /// instead of rendering progress bars, for frontends drawing their own.
+  ///
+  /// Sending waits for the receiver, which should keep up with the downloads;
+  /// dropping it stops the events but not the run.
+  pub async fn run_with_events(
+    mut self,
+    events: mpsc::Sender<ProgressEvent>,
+  ) -> Result<RunSummary> {
+    self.events = Some(events);
+    self.progress = false;
+    self.run().await
+  }
+
+  /// Run the downloader and return a [`RunSummary`] of the downloads
+  ///
+  /// Deletes the `self.dest` directory if `self.clean` is true
+  /// Creates the `self.dest` directory if it does not exist
+  ///
+  /// Downloads files concurrently using `self.workers` workers
+  /// Individual download failures are counted in the summary, `Err` is only
+  /// returned when the run itself fails
+  /// URLs streamed with [`Self::with_url_stream`] are downloaded as they
+  /// arrive, the run completes once the stream closes
+  pub async fn run(mut self) -> Result<RunSummary> {
+    if self.dest_is_file {
+      self.use_dest_as_file()?;
+    } else if self.dest.is_file() {
+      return Err(error::DownloadError::DestDirError(format!(
+        "{} is a file, not a directory (use --dest-is-file to download a \
+         single URL to it)",
+        self.dest.display()
+      )));
+    }
+    self.disambiguate_filenames();
+    if self.clean {
+      fs::remove_dir_all(&self.dest).unwrap_or(());
+    }
+    fs::create_dir_all(&self.dest)?;
+
+    // Keep the rate limiter in step with the bandwidth schedule, falling
+    // back to the max rate outside of its windows
+    let scheduler = self.rate_schedule.clone().map(|schedule| {
+      let limiter = self.rate_limiter.clone();
+      let max_rate = self.max_rate;
+      tokio::spawn(async move {
+        loop {
+          limiter.set_rate(schedule.current_rate().or(max_rate));
+          tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+        }
+      })
+    });
+
+    let urls = self.scheduled_urls().await;
+    // Download one URL per ETag, the others are linked to it afterwards
+    let (urls, etag_duplicates) = if self.dedup_by_etag {
+      self.group_by_etag(urls).await
+    } else {
+      (urls, Vec::new())
+    };
+
+    let mp = Arc::new(if self.progress {
+      MultiProgress::new()
+    } else {
+      MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    });
+    mp.set_alignment(MultiProgressAlignment::Top);
+    let total_files = urls.len() as u64;
+    let total_pb = mp.add(ProgressBar::new(total_files));
+    let downloader = Arc::new(self.clone());
+
+    // Total progress bar tracking files completed
+    total_pb.set_style(
+      ProgressStyle::default_bar()
+        .template(&progress::total_template())?
+        .progress_chars("#>-"),
+    );
+    total_pb
+      .set_message(human_readable_size(*downloader.total_size.lock().await));
+
+    // Create tasks with worker limit, ramped up one permit at a time when
+    // configured so connections open staggered
+    let ramp = self.concurrency_ramp.filter(|_| self.workers > 1);
+    let permits = if ramp.is_some() {
+      1
+    } else {
+      self.workers
+    };
+    let semaphore = Arc::new(Semaphore::new(permits));
+    let ramp = ramp.map(|ramp| {
+      let semaphore = semaphore.clone();
+      let workers = self.workers;
+      let step = ramp / workers as u32;
+      tokio::spawn(async move {
+        for _ in 1..workers {
+          tokio::time::sleep(step).await;
+          semaphore.add_permits(1);
+        }
+      })
+    });
+    let health = self.health_interval.map(|interval| {
+      let downloader = downloader.clone();
+      let total_pb = total_pb.clone();
+      tokio::spawn(
+        async move { downloader.log_health(interval, total_pb).await },
+      )
+    });
+    let mut tasks = urls
+      .clone()
+      .into_iter()
+      .map(|url| {
+        downloader.clone().process_url(
+          url,
+          mp.clone(),
+          total_pb.clone(),
+          semaphore.clone(),
+        )
+      })
+      .collect::<task::JoinSet<_>>();
+
+    // Collect a finished download, aborting the remaining ones when it failed
+    // with --fail-fast. Returns whether they were aborted.
+    type Finished = (DownloadResult, Result<DownloadOutcome>);
+    let fail_fast = self.fail_fast;
+    let collect = |joined: std::result::Result<Finished, task::JoinError>,
+                   tasks: &mut task::JoinSet<Finished>,
+                   results: &mut Vec<Finished>| {
+      let finished = match joined {
+        Ok(finished) => finished,
+        Err(e) if e.is_cancelled() => return false,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+      };
+      let abort = fail_fast && finished.1.is_err();
+      results.push(finished);
+      if abort {
+        info!("Aborting the remaining downloads after a failure (--fail-fast)");
+        tasks.abort_all();
+      }
+      abort
+    };
+
+    // Schedule streamed URLs as they arrive until the input closes, keeping
+    // at most two scheduled downloads per worker
+    let schedule = async {
+      let mut urls = urls;
+      let mut results = Vec::new();
+      let mut aborted = false;
+      if let Some(stream) = &downloader.url_stream {
+        let mut stream = stream.lock().await;
+        while !aborted && let Some(url) = stream.recv().await {
+          downloader.touch();
+          let url = match Url::parse(url.trim()) {
+            Ok(url) => url.to_string(),
+            Err(e) => {
+              tracing::warn!("Skipping invalid URL {}: {}", url, e);
+              continue;
+            },
+          };
+          if !downloader.is_scheduled(&url) {
+            continue;
+          }
+          while tasks.len() >= downloader.workers * 2
+            && let Some(joined) = tasks.join_next().await
+          {
+            aborted |= collect(joined, &mut tasks, &mut results);
+          }
+          if aborted {
+            break;
+          }
+          total_pb.inc_length(1);
+          urls.push(url.clone());
+          tasks.spawn(downloader.clone().process_url(
+            url,
+            mp.clone(),
+            total_pb.clone(),
+            semaphore.clone(),
+          ));
+        }
+      }
+      while let Some(joined) = tasks.join_next().await {
+        collect(joined, &mut tasks, &mut results);
+      }
+      (urls, results)
+    };
+
+    // Wait for all downloads, dropping the tasks aborts them and keeps the
+    // partials for resuming
+    downloader.touch();
+    let (urls, results) = match self.max_idle_time {
+      Some(max_idle) => tokio::select! {
+        results = schedule => results,
+        _ = downloader.idle_watchdog(max_idle) => {
+          for handle in [scheduler, ramp, health].into_iter().flatten() {
+            handle.abort();
+          }
+          mp.clear().unwrap_or(());
+          return Err(error::DownloadError::IdleTimeout(max_idle));
+        },
+      },
+      None => schedule.await,
+    };
+    let mut summary = RunSummary {
+      total: urls.len(),
+      aborted: urls.len() - results.len(),
+      ..Default::default()
+    };
+    for (result, res) in results {
+      summary.results.push(result);
+      match res {
+        Ok(outcome) => {
+          summary.succeeded += 1;
+          match outcome {
+            DownloadOutcome::Downloaded => summary.downloaded += 1,
+            DownloadOutcome::Resumed => summary.resumed += 1,
+            _ => summary.unchanged += 1,
+          }
+        },
+        Err(error::DownloadError::HostUnavailable(_)) => summary.skipped += 1,
+        Err(e) => {
+          summary.failed += 1;
+          summary.disk_full |= e.is_disk_full();
+        },
+      }
+    }
+    for (original, duplicate) in &etag_duplicates {
+      summary.total += 1;
+      let res = downloader
+        .link_duplicate(original, duplicate)
+        .map(|_| DownloadOutcome::Skipped);
+      match &res {
+        Ok(_) => {
+          summary.succeeded += 1;
+          summary.unchanged += 1;
+        },
+        Err(e) => {
+          tracing::error!(
+            "Failed to link {} to {}: {:?}",
+            duplicate,
+            original,
+            e
+          );
+          summary.failed += 1;
+        },
+      }
+      summary.results.push(downloader.download_result(
+        duplicate,
+        &res,
+        std::time::Duration::ZERO,
+      ));
+    }
+    if summary.skipped > 0 {
+      info!("Skipped {} URLs because their host was down", summary.skipped);
+    }
+    summary.extraction_failures =
+      downloader.extraction_failures.load(std::sync::atomic::Ordering::Relaxed);
+    for (url, count) in downloader.throttled.lock().await.iter() {
+      let host =
+        Url::parse(url).map(|u| u.authority().to_string()).unwrap_or_default();
+      *summary.throttled.entry(host).or_default() += count;
+    }
+    for (host, count) in &summary.throttled {
+      info!("Rate limited (429) {} times by {}", count, host);
+    }
+    summary.retries = downloader.retries.snapshot();
+
+    for handle in [scheduler, ramp, health].into_iter().flatten() {
+      handle.abort();
+    }
+
+    // Finish total progress bar
+    total_pb.finish_with_message(human_readable_size(
+      *downloader.total_size.lock().await,
+    ));
+
+    if let Some(mode) = downloader.dedup_content {
+      downloader.dedup_content(&urls, mode).await?;
+    }
+    if let Some(lock) = &downloader.manifest_lock {
+      downloader.finish_manifest_lock(lock, &summary).await?;
+    }
+    if let Some(webhook) = &downloader.webhook {
+      let event = WebhookEvent::Batch {
+        summary: summary.clone(),
+      };
+      webhook.send(&event).await;
+    }
+    Ok(summary)
+  }
+
+  /// Hash the completed downloads of `urls` and, depending on `mode`, replace
+  /// files whose content duplicates an earlier download with a hardlink or
+  /// symlink to it.
+  ///
+  /// Returns the `(original, duplicate)` pairs found.
+  #[tracing::instrument(skip(self, urls), err(level = tracing::Level::ERROR))]
+  async fn dedup_content(
+    &self,
+    urls: &[String],
+    mode: DedupMode,
+  ) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut paths = Vec::new();
+    for url in urls {
+      let path = self.dest.join(self.local_filename(url));
+      if path.is_file() && !paths.contains(&path) {
+        paths.push(path);
+      }
+    }
+    let duplicates =
+      task::spawn_blocking(move || checksum::find_duplicates(&paths))
+        .await
+        .map_err(std::io::Error::other)?;
+
+    let mut saved = 0;
+    for (original, duplicate) in &duplicates {
+      info!(
+        "Duplicate content: {} is identical to {}",
+        duplicate.display(),
+        original.display()
+      );
+      let size = duplicate.metadata().map(|m| m.len()).unwrap_or(0);
+      match mode {
+        DedupMode::Report => continue,
+        DedupMode::Hardlink => {
+          fs::remove_file(duplicate)?;
+          fs::hard_link(original, duplicate)?;
+        },
+        DedupMode::Symlink => {
+          fs::remove_file(duplicate)?;
+          symlink_file(original, duplicate)?;
+        },
+      }
+      saved += size;
+    }
+    if !duplicates.is_empty() {
+      info!(
+        "Found {} duplicate files, saved {}",
+        duplicates.len(),
+        human_readable_size(saved)
+      );
+    }
+    Ok(duplicates)
+  }
+}
+
+/// Path of the partial download of `path`
+fn partial_path(path: &std::path::Path) -> PathBuf {
+  path.with_extension(format!(
+    "{}.part",
+    path.extension().unwrap_or_default().to_string_lossy()
+  ))
+}
+
+/// Compute the `algorithm` digest of the file at `path` off the async runtime
+async fn digest_file(
+  path: &std::path::Path,
+  algorithm: Algorithm,
+) -> Result<String> {
+  let path = path.to_path_buf();
+  Ok(
+    task::spawn_blocking(move || checksum::digest_file(&path, algorithm))
+      .await
+      .map_err(std::io::Error::other)??,
+  )
+}
+
+/// `name` with the first counter appended before its extension that makes it
+/// not `taken`, counting in the filename rather than in the directories it is
+/// nested in
+fn numbered(name: &str, taken: impl Fn(&str) -> bool) -> String {
+  let (dirs, file) = match name.rsplit_once('/') {
+    Some((dirs, file)) => (format!("{}/", dirs), file),
+    None => (String::new(), name),
+  };
+  (1..)
+    .map(|n| {
+      filename::add_affixes(
+        file,
+        &dirs,
+        &format!("_{}", n),
+        cli::SuffixPlacement::BeforeExt,
+      )
+    })
+    .find(|unique| !taken(unique))
+    .unwrap()
+}
+
+/// Number of ranges to fetch a file of `total` bytes in: one below
+/// `threshold`, otherwise `split` capped so no range is smaller than
+/// `min_chunk`
+fn split_count(split: u64, total: u64, threshold: u64, min_chunk: u64) -> u64 {
+  if total < threshold {
+    return 1;
+  }
+  split.min(total / min_chunk.max(1)).max(1)
+}
+
+/// Path of segment `i` of the split partial download at `temp_filepath`
+fn segment_path(temp_filepath: &std::path::Path, i: usize) -> PathBuf {
+  let mut path = temp_filepath.as_os_str().to_owned();
+  path.push(format!(".{}", i));
+  PathBuf::from(path)
+}
+
+/// Path of the range list of the split partial download at `temp_filepath`
+fn ranges_sidecar(temp_filepath: &std::path::Path) -> PathBuf {
+  let mut path = temp_filepath.as_os_str().to_owned();
+  path.push(".ranges");
+  PathBuf::from(path)
+}
+
+/// Write `ranges` to `path`, one `start-end` per line
+fn write_ranges(path: &std::path::Path, ranges: &[(u64, u64)]) -> Result<()> {
+  let text = ranges
+    .iter()
+    .map(|(start, end)| format!("{}-{}\n", start, end))
+    .collect::<String>();
+  Ok(fs::write(path, text)?)
+}
+
+/// Read the ranges written by [`write_ranges`], `None` if the file is missing
+/// or does not hold contiguous ranges starting at 0
+fn read_ranges(path: &std::path::Path) -> Option<Vec<(u64, u64)>> {
+  let text = fs::read_to_string(path).ok()?;
+  let ranges = text
+    .lines()
+    .map(|line| {
+      let (start, end) = line.trim().split_once('-')?;
+      Some((start.parse().ok()?, end.parse().ok()?))
+    })
+    .collect::<Option<Vec<(u64, u64)>>>()?;
+  let contiguous = ranges.first().is_some_and(|r| r.0 == 0)
+    && ranges.iter().all(|r| r.0 <= r.1)
+    && ranges.windows(2).all(|w| w[1].0 == w[0].1 + 1);
+  contiguous.then_some(ranges)
+}
+
+/// Path of the ETag cache sidecar of the download at `path`
+fn etag_sidecar(path: &std::path::Path) -> PathBuf {
+  let mut name = path.file_name().unwrap_or_default().to_os_string();
+  name.push(".etag");
+  path.with_file_name(name)
+}
+
+/// Create a symlink at `link` pointing to `original`
+fn symlink_file(
+  original: &std::path::Path,
+  link: &std::path::Path,
+) -> std::io::Result<()> {
+  #[cfg(unix)]
+  {
+    std::os::unix::fs::symlink(original, link)
+  }
+  #[cfg(windows)]
+  {
+    std::os::windows::fs::symlink_file(original, link)
+  }
+}
+
+/// Parse the complete length from a `Content-Range` header value such as
+/// `bytes 1000-4999/5000`, `None` when it is unknown (`*`)
+pub fn parse_content_range_total(value: &str) -> Option<u64> {
+  value.trim().strip_prefix("bytes")?.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+/// Whether a 403 response body is an object store's "presigned URL expired"
+/// error (S3, GCS and Azure use recognizable XML/JSON error codes for this).
+pub fn is_presigned_url_expired(body: &str) -> bool {
+  const MARKERS: &[&str] = &[
+    "Request has expired",
+    "<Code>ExpiredToken</Code>",
+    "\"ExpiredToken\"",
+    "The provided token has expired",
+    "Signature not valid in the specified time frame",
+    "Signed expiry time",
+  ];
+  MARKERS.iter().any(|m| body.contains(m))
+}
+
+/// Convert bytes to human-readable format
+pub fn human_readable_size(bytes: u64) -> String {
+  use humansize::{format_size, DECIMAL};
+  format_size(bytes, DECIMAL)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn small_files_are_not_split() {
+    assert_eq!(split_count(4, 0, 0, 1), 1);
+    assert_eq!(split_count(4, 1000, 1001, 1), 1);
+    assert_eq!(split_count(4, 1000, 1000, 1), 4);
+  }
+
+  #[test]
+  fn split_count_keeps_chunks_above_the_minimum() {
+    let mib = 1 << 20;
+    assert_eq!(split_count(8, 3 * mib, mib, mib), 3);
+    assert_eq!(split_count(8, 3 * mib - 1, mib, mib), 2);
+    assert_eq!(split_count(8, 100 * mib, mib, mib), 8);
+    assert_eq!(split_count(1, 100 * mib, mib, mib), 1);
+    assert_eq!(split_count(8, 10, 0, 0), 8);
+  }
+}
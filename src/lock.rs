@@ -0,0 +1,97 @@
+use std::{
+  collections::BTreeMap,
+  path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+/// Current lock file format version
+const LOCK_VERSION: u32 = 1;
+
+/// How the manifest lock file is used
+#[derive(Debug, Clone)]
+pub struct ManifestLock {
+  /// Path of the lock file
+  pub path:   PathBuf,
+  /// Verify the run against the existing lock instead of writing it
+  pub frozen: bool,
+}
+
+/// What was downloaded for a single URL
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockEntry {
+  /// URL the content was served from, after redirects
+  pub final_url: Option<String>,
+  /// `ETag` the server sent for the content
+  pub etag:      Option<String>,
+  /// Size in bytes
+  pub size:      u64,
+  /// Hex encoded SHA-256 digest
+  pub sha256:    String,
+}
+
+/// Lock file recording exactly what every URL of a run resolved to, so a
+/// later `--frozen` run can verify nothing drifted
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Lockfile {
+  pub version: u32,
+  /// Entries keyed by requested URL
+  pub entries: BTreeMap<String, LockEntry>,
+}
+
+impl Lockfile {
+  /// Create a lock file from `entries`
+  pub fn new(entries: BTreeMap<String, LockEntry>) -> Self {
+    Self {
+      version: LOCK_VERSION,
+      entries,
+    }
+  }
+
+  /// Read a lock file from `path`
+  pub fn load(path: &Path) -> Result<Self> {
+    let json = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json).map_err(std::io::Error::other)?)
+  }
+
+  /// Write this lock file as pretty JSON to `path`
+  pub fn save(&self, path: &Path) -> Result<()> {
+    let json =
+      serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+    std::fs::write(path, json)?;
+    Ok(())
+  }
+
+  /// Describe every way `actual` differs from this lock, empty when it matches
+  pub fn drift(&self, actual: &Lockfile) -> Vec<String> {
+    let mut drift = Vec::new();
+    for (url, locked) in &self.entries {
+      let Some(entry) = actual.entries.get(url) else {
+        drift.push(format!("{}: not downloaded", url));
+        continue;
+      };
+      if entry.sha256 != locked.sha256 {
+        drift.push(format!(
+          "{}: checksum {} does not match locked {}",
+          url, entry.sha256, locked.sha256
+        ));
+      }
+      if let (Some(etag), Some(locked_etag)) = (&entry.etag, &locked.etag)
+        && etag != locked_etag
+      {
+        drift.push(format!(
+          "{}: ETag {} does not match locked {}",
+          url, etag, locked_etag
+        ));
+      }
+    }
+    for url in actual.entries.keys() {
+      if !self.entries.contains_key(url) {
+        drift.push(format!("{}: not in lock file", url));
+      }
+    }
+    drift
+  }
+}
@@ -1,526 +1,317 @@
-mod cli;
-mod error;
-mod utils;
-
-shadow_rs::shadow!(build);
-
-use std::{
-  collections::HashSet,
-  fs::{self, File},
-  io::Write,
-  path::PathBuf,
-  sync::Arc,
-};
+use std::{fs, path::PathBuf};
 
 use futures::StreamExt;
-use indicatif::{
-  MultiProgress,
-  MultiProgressAlignment,
-  ProgressBar,
-  ProgressStyle,
+use multifiledownloader::{
+  build,
+  cli::{self, Cli, ReportFormat},
+  client::ClientOptions,
+  error::Result,
+  ratelimit,
+  report::ResultsLog,
+  select,
+  summary,
+  utils,
+  Downloader,
 };
-use reqwest::Client;
-use tokio::task;
 use tracing::info;
-use url::Url;
-
-use crate::{cli::Cli, error::Result};
 
-// Struct to hold downloader configuration and state
-#[derive(Clone)]
-pub struct Downloader {
-  urls:       Vec<String>,
-  dest:       PathBuf,
-  workers:    usize,
-  client:     Client,
-  total_size: Arc<tokio::sync::Mutex<u64>>,
-  clean:      bool,
-  seen_urls:  Arc<tokio::sync::Mutex<HashSet<String>>>,
-}
-
-impl std::fmt::Debug for Downloader {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    let truncate = if self.urls.len() > 3 {
-      3
-    } else {
-      self.urls.len()
-    };
-    let urls = self.urls.clone().into_iter().take(truncate).collect::<Vec<_>>();
-    let urls = format!(
-      "[{}{}; {}]",
-      urls.join(", "),
-      if self.urls.len() > truncate {
-        "..."
-      } else {
-        ""
+/// Report invalid (and with `--check-reachable`, unreachable) URLs by where
+/// they came from and return the exit code
+async fn validate_urls(cli: &Cli) -> Result<i32> {
+  let entries = cli.get_url_entries()?;
+  let mut problems = 0;
+  let mut valid = Vec::new();
+  for (source, entry) in &entries {
+    let (url, _) = utils::split_output_name(entry);
+    let url = &url.to_string();
+    match utils::validate_url(url) {
+      Ok(_) => valid.push((source, url.clone())),
+      Err(e) => {
+        problems += 1;
+        println!("{}: invalid URL {}: {}", source, url, e);
       },
-      self.urls.len()
-    );
-    f.debug_struct("Downloader")
-      .field("urls", &urls)
-      .field("dest", &self.dest)
-      .field("workers", &self.workers)
-      .field("total_size", &self.total_size)
-      .field("clean", &self.clean)
-      .finish()
-  }
-}
-
-impl Default for Downloader {
-  fn default() -> Self {
-    Self {
-      urls:       Default::default(),
-      dest:       PathBuf::from(".")
-        .canonicalize()
-        .unwrap_or_else(|_| PathBuf::from(".")),
-      workers:    std::thread::available_parallelism().unwrap().get(),
-      client:     Default::default(),
-      total_size: Default::default(),
-      clean:      true,
-      seen_urls:  Default::default(),
     }
   }
-}
-
-/// Downloader implementation
-impl Downloader {
-  /// Create a new Downloader
-  pub fn new(
-    urls: Vec<String>,
-    dest: String,
-    workers: usize,
-    clean: bool,
-  ) -> Self {
-    let dest = shellexpand::tilde(&dest).to_string();
-    let dest = PathBuf::from(dest.clone())
-      .canonicalize()
-      .unwrap_or_else(|_| PathBuf::from(dest.clone()));
-    let client = Client::new();
-    let total_size = Arc::new(tokio::sync::Mutex::new(0));
-    let seen_urls = Arc::new(tokio::sync::Mutex::new(HashSet::new()));
-
-    Downloader {
-      urls,
-      dest,
-      workers,
-      client,
-      total_size,
-      clean,
-      seen_urls,
-    }
-  }
-
-  /// Get the filename from a given URL.
-  /// If the URL is invalid or the url has no path segments, return
-  /// "downloaded_file"
-  pub fn get_filename(url: &str) -> String {
-    Url::parse(url)
-      .ok()
-      .and_then(|u| {
-        u.path_segments()
-          .map(|mut s| s.next_back().unwrap_or("downloaded_file").to_string())
+  if cli.get_check_reachable() {
+    let downloader = Downloader::new(
+      Vec::new(),
+      cli.get_dest(),
+      cli.get_workers(),
+      false,
+      cli.get_client_options(),
+    )?;
+    let downloader = &downloader;
+    let results = futures::stream::iter(valid)
+      .map(|(source, url)| async move {
+        (source, downloader.get_file_size(&url).await.err(), url)
       })
-      .unwrap_or_else(|| "downloaded_file".to_string())
-  }
-
-  /// Shell Expand tilde in string
-  pub fn shellexpand_tilde<T: AsRef<str>>(s: T) -> String {
-    shellexpand::tilde(s.as_ref()).to_string()
-  }
-
-  /// Shell Expand environment variables and tilde home directory in string
-  pub fn shellexpand_full<T: AsRef<str>>(s: T) -> String {
-    use std::{borrow::Cow, env};
-    fn context(s: &str) -> Option<Cow<'static, str>> {
-      match env::var(s) {
-        Ok(value) => Some(value.into()),
-        Err(env::VarError::NotPresent) => Some("".into()),
-        Err(_) => Some("".into()),
+      .buffered(cli.get_workers().max(1))
+      .collect::<Vec<_>>()
+      .await;
+    for (source, err, url) in results {
+      if let Some(e) = err {
+        problems += 1;
+        println!("{}: unreachable URL {}: {}", source, url, e);
       }
     }
-    fn home_dir() -> Option<String> {
-      env::var("HOME").ok()
-    }
-    shellexpand::full_with_context_no_errors(s.as_ref(), home_dir, context)
-      .to_string()
-  }
-
-  pub fn num_workers(&self) -> usize {
-    self.workers
   }
+  info!("Checked {} URLs, {} problems", entries.len(), problems);
+  Ok(if problems > 0 {
+    summary::EXIT_ERROR
+  } else {
+    summary::EXIT_SUCCESS
+  })
+}
 
-  /// Get the number of URLs
-  pub fn num_urls(&self) -> usize {
-    self.urls.len()
-  }
+/// Main entry point
+fn main() -> Result<()> {
+  // Must run before the runtime starts its worker threads
+  ratelimit::init_local_offset();
+  run()
+}
 
-  #[allow(dead_code)]
-  pub fn get_dest(&self) -> PathBuf {
-    self.dest.clone()
-  }
+#[tokio::main]
+async fn run() -> Result<()> {
+  use clap::{CommandFactory, Parser};
+  let mut cmd = Cli::command();
+  let cli = Cli::parse();
+  utils::init_tracing(cli.get_trace_chunks(), cli.get_quiet());
+  info!("Multi File Downloader v{}", build::PKG_VERSION);
 
-  #[allow(dead_code)]
-  /// Set the list of URLs
-  pub fn with_urls(mut self, urls: Vec<String>) -> Self {
-    self.urls = urls
-      .into_iter()
-      .map(|s| s.trim().to_string())
-      .filter(|s| !s.is_empty())
-      .filter_map(|s| Url::parse(&s).ok().map(|u| u.to_string()))
-      .collect::<Vec<_>>();
-    self
+  if let Some(shell) = cli.completion {
+    cli::generate_completions("multifiledownloader", shell.as_ref(), &mut cmd);
+    return Ok(());
   }
 
-  #[allow(dead_code)]
-  /// Set the number of worker threads
-  pub fn with_workers(mut self, workers: usize) -> Self {
-    self.workers = workers;
-    self
+  if cli.get_print_schema() {
+    let schema =
+      serde_json::to_string_pretty(&summary::RunSummary::json_schema())
+        .map_err(std::io::Error::other)?;
+    println!("{}", schema);
+    return Ok(());
   }
 
-  #[allow(dead_code)]
-  /// Set the destination directory
-  pub fn with_dest<T: AsRef<str>>(mut self, dest: T) -> Self {
-    let dest = shellexpand::tilde(dest.as_ref()).to_string();
-    let dest = PathBuf::from(dest.clone())
-      .canonicalize()
-      .unwrap_or_else(|_| PathBuf::from(dest.clone()));
-    self.dest = dest;
-    self
+  if cli.get_validate_urls_only() {
+    let code = match validate_urls(&cli).await {
+      Ok(code) => code,
+      Err(e) => {
+        tracing::error!("Failed to read URLs: {}", e);
+        e.exit_code()
+      },
+    };
+    std::process::exit(code);
   }
 
-  #[allow(dead_code)]
-  /// Set the reqwest client
-  pub fn with_client(mut self, client: Client) -> Self {
-    self.client = client;
-    self
+  let ((urls, output_names), skip_list) = match cli
+    .get_urls()
+    .and_then(|urls| cli.get_skip_list().map(|skip_list| (urls, skip_list)))
+  {
+    Ok(lists) => lists,
+    Err(e) => {
+      tracing::error!("Failed to read URLs: {}", e);
+      std::process::exit(e.exit_code());
+    },
+  };
+  if urls.is_empty() && !cli.get_stream_input() {
+    eprintln!("Error: No URLs provided");
+    std::process::exit(1);
   }
 
-  #[allow(dead_code)]
-  /// Enable file cleanup
-  pub fn clean(mut self) -> Self {
-    self.clean = true;
-    self
+  let dest = if cli.get_timestamped_dir() {
+    let base = PathBuf::from(cli.get_dest());
+    let dest = if cli.get_dry_run() {
+      base.join(utils::timestamp_dirname())
+    } else {
+      utils::create_timestamped_dir(&base)?
+    };
+    info!("Using timestamped destination {}", dest.display());
+    dest.to_string_lossy().to_string()
+  } else {
+    cli.get_dest()
+  };
+
+  if !cli.verify_tls_hostname {
+    tracing::warn!(
+      "TLS hostname verification is DISABLED, connections can be intercepted"
+    );
+    eprintln!(
+      "\x1b[91mWARNING: TLS hostname verification is disabled \
+       (--verify-tls-hostname false)\x1b[0m"
+    );
   }
-
-  #[allow(dead_code)]
-  /// Disable file cleanup
-  pub fn no_clean(mut self) -> Self {
-    self.clean = false;
-    self
+  if cli.insecure {
+    tracing::warn!(
+      "TLS certificate verification is DISABLED, connections can be \
+       intercepted"
+    );
+    eprintln!(
+      "\x1b[91mWARNING: TLS certificate verification is disabled \
+       (--insecure), any certificate is accepted\x1b[0m"
+    );
   }
 
-  #[allow(dead_code)]
-  /// Get the total size of all downloaded files
-  pub async fn get_total_size_bytes(&self) -> u64 {
-    *self.total_size.lock().await
+  #[cfg(not(unix))]
+  if cli.get_file_mode().is_some() {
+    tracing::warn!("--file-mode is only supported on Unix, ignoring it");
   }
 
-  #[allow(dead_code)]
-  /// Get the total size of all downloaded files
-  pub async fn get_total_size_human(&self) -> String {
-    human_readable_size(*self.total_size.lock().await)
+  #[allow(unused_mut)]
+  let mut client_opts = cli.get_client_options();
+  #[cfg(feature = "pac")]
+  {
+    client_opts.pac_script = cli.load_pac_script().await?;
   }
 
-  /// Get file size of the file at `url` from http HEAD request
-  #[tracing::instrument(skip(self), fields(url), err(level = tracing::Level::ERROR))]
-  async fn get_file_size(&self, url: &str) -> Result<u64> {
-    let resp = self.client.head(url).send().await?;
-    // Retry on 429
-    if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
-      let random_t = rand::random_range(500..1500);
-      tokio::time::sleep(tokio::time::Duration::from_millis(random_t)).await;
-      return Box::pin(self.get_file_size(url)).await;
-    }
-    // Handle error
-    match resp.error_for_status_ref() {
-      Ok(_) => (),
-      Err(e) => return Err(error::DownloadError::ReqwestError(e)),
-    }
-    // Get content length from response or response headers
-    let content_len = resp
-      .headers()
-      .get("content-length")
-      .map(|v| v.to_str().unwrap().parse::<u64>().unwrap())
-      .or(resp.content_length())
-      .unwrap_or(0);
-    if resp.status().is_success() {
-      if !self.seen_urls.lock().await.contains(url) {
-        // Update total size and seen urls
-        self.seen_urls.lock().await.insert(url.to_string());
-        *self.total_size.lock().await += content_len;
+  let redirect_client = cli
+    .get_trace_redirects()
+    .then(|| {
+      ClientOptions {
+        no_redirects: true,
+        ..client_opts.clone()
       }
-      return Ok(content_len);
-    }
-    Ok(content_len)
+      .build()
+    })
+    .transpose()?;
+  let downloader = Downloader::new(
+    urls,
+    dest,
+    cli.get_workers(),
+    cli.get_clean(),
+    client_opts,
+  )?
+  .with_redirect_client(redirect_client)
+  .with_refresh_cmd(cli.get_refresh_cmd())
+  .with_dedup_content(cli.get_dedup_content())
+  .with_read_chunk_size(cli.get_read_chunk_size())
+  .with_resume_metadata_ttl(cli.get_resume_metadata_ttl())
+  .with_filename_options(cli.get_filename_options())
+  .with_provenance(cli.get_provenance())
+  .with_rate_schedule(cli.get_rate_schedule())
+  .with_host_failure_threshold(
+    cli.get_host_failure_threshold(),
+    cli.get_host_cooldown(),
+  )
+  .with_sort_by_size(cli.get_sort_by_size())
+  .with_only_extensions(cli.get_only_extensions())
+  .with_exclude_extensions(cli.get_exclude_extensions())
+  .with_resume_from(cli.get_resume_from())
+  .with_crawl(cli.get_crawl_options())
+  .with_resume_partial_threshold(cli.get_resume_partial_threshold())
+  .with_file_mode(cli.get_file_mode())
+  .with_head_timeout(cli.get_head_timeout())
+  .with_manifest_lock(cli.get_manifest_lock())
+  .with_extract(cli.get_extract())
+  .with_resume_check_remote(cli.get_resume_check_remote())
+  .with_disk_retries(cli.get_disk_retries())
+  .with_remote_checksums(cli.get_remote_checksums())
+  .with_expected_checksums(cli.get_checksum_file()?)
+  .with_interleave_hosts(cli.get_interleave_hosts())
+  .with_webhook(cli.get_webhook())
+  .with_max_idle_time(cli.get_max_idle_time())
+  .with_concurrency_ramp(cli.get_concurrency_ramp())
+  .with_timeout_retries(cli.get_timeout_retries())
+  .with_skip_list(skip_list)
+  .with_auth(cli.get_auth())
+  .with_max_rate(cli.get_max_rate())
+  .with_resume_index(cli.get_resume_index())
+  .with_split(cli.get_split())
+  .with_split_threshold(cli.get_split_threshold())
+  .with_min_chunk_size(cli.get_min_chunk_size())
+  .with_dedup_by_etag(cli.get_dedup_by_etag())
+  .with_trace_chunks(cli.get_trace_chunks())
+  .with_max_response_header_bytes(Some(cli.get_max_response_header_bytes()))
+  .with_existing_policy(cli.get_existing_policy())
+  .with_etag_cache(!cli.get_no_cache())
+  .with_url_stream(cli.get_stream_input().then(|| {
+    let stdin = std::io::BufReader::new(std::io::stdin());
+    utils::stream_lines(stdin, cli.get_workers())
+  }))
+  .with_results_log(
+    cli
+      .get_output_json_lines_append()
+      .map(|path| ResultsLog::open(&path))
+      .transpose()?,
+  )
+  .with_fsync(cli.get_fsync())
+  .with_fail_fast(cli.get_fail_fast())
+  .with_retries(cli.get_max_retries(), cli.get_retry_backoff())
+  .with_checksum_retries(cli.get_retry_on_checksum_failure())
+  .with_dest_is_file(cli.get_dest_is_file())
+  .with_health_interval(cli.get_health_interval())
+  .with_per_host(cli.get_per_host())
+  .with_output_names(output_names)
+  .with_max_concurrent_writes(cli.get_max_concurrent_writes())
+  .with_progress(!cli.get_quiet() && !cli.get_no_progress())
+  .with_include_headers_in_report(cli.get_include_headers_in_report());
+  // Select on the configured downloader so the HEAD pre-pass uses the same
+  // credentials and limits as the run
+  let downloader = if cli.get_interactive_select() {
+    select::interactive_select(downloader).await?
+  } else {
+    downloader
   }
-
-  /// Download a single file at `url` and show progress bar in `mp` and updating
-  /// `total_pb`.
-  ///
-  /// Returns Ok(()) on success
-  ///
-  /// Skips file if it already exists
-  /// Resumes download if file already exists and is partially downloaded
-  #[tracing::instrument(skip(self, mp, total_pb), fields(url), err(level = tracing::Level::ERROR))]
-  pub async fn download_file(
-    &self,
-    url: String,
-    mp: Arc<MultiProgress>,
-    total_pb: ProgressBar,
-  ) -> Result<()> {
-    let filename = Self::get_filename(&url);
-    let filepath = self.dest.join(&filename);
-    let temp_filepath = filepath.with_extension(format!(
-      "{}.part",
-      filepath.extension().unwrap_or_default().to_string_lossy()
-    ));
-    // Skip if file exists
-    if filepath.exists() {
-      let pb = mp.add(ProgressBar::new(0));
-      pb.finish_with_message(format!(
-        "\x1b[93mExists\x1b[0m {} {}",
-        filename, "✔"
-      ));
-      total_pb.inc(1); // Increment total progress for skipped files
-      tokio::time::sleep(tokio::time::Duration::from_millis(
-        rand::random_range(200..500),
-      ))
-      .await;
-      pb.finish_and_clear();
-      return Ok(());
-    }
-
-    // Get existing size for resume
-    let start_byte = temp_filepath.metadata().map(|m| m.len()).unwrap_or(0);
-    let mut file_total_size = self.get_file_size(&url).await?;
-    // Update total size message for total progress bar tracker
-    total_pb.set_message(human_readable_size(*self.total_size.lock().await));
-
-    // Setup progress bar
-    let pb = mp.add(ProgressBar::new(file_total_size));
-    pb.set_style(
-      ProgressStyle::default_bar()
-        .template(
-          "[{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} \
-           ({eta}) {msg}",
-        )?
-        .progress_chars("+>-"),
-    );
-    pb.set_message(format!(
-      "\x1b[93m{}\x1b[0m  {}",
-      human_readable_size(file_total_size),
-      filename,
-    ));
-
-    // Check if Resume download done
-    if start_byte > 0 {
-      pb.set_position(start_byte);
-      if start_byte >= file_total_size {
-        total_pb.inc(1); // Increment total progress for completed partials
-        fs::rename(&temp_filepath, &filepath).unwrap_or(());
-        pb.set_position(start_byte);
-        pb.finish_with_message(format!(
-          "\x1b[96mDone\x1b[0m \x1b[92m{}\x1b[0m  {} {}",
-          human_readable_size(file_total_size),
-          filename,
-          "✔",
-        ));
-        tokio::time::sleep(tokio::time::Duration::from_millis(
-          rand::random_range(500..1000),
-        ))
-        .await;
-        pb.finish_and_clear();
-        return Ok(());
-      }
-    }
-
-    // Setup request with range header for resume
-    let resp = self
-      .client
-      .get(&url)
-      .header("Range", format!("bytes={}-", start_byte))
-      .send()
-      .await?;
-
-    // Retry on 429
-    if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
-      let random_t = rand::random_range(1000..3000);
-      let retry_after = resp
-        .headers()
-        .get("retry-after")
-        .and_then(|v| v.to_str().ok().and_then(|s| s.parse::<u64>().ok()))
-        .or(Some(random_t));
-      if let Some(retry_after) = retry_after {
-        pb.finish_and_clear();
-        tokio::time::sleep(tokio::time::Duration::from_millis(retry_after))
-          .await;
-        return Box::pin(self.download_file(url, mp, total_pb)).await;
-      }
-    }
-
-    // Handle other http error
-    match resp.error_for_status_ref() {
-      Ok(_) => (),
-      Err(e) => return Err(error::DownloadError::ReqwestError(e)),
-    }
-
-    // Update total size if not already determined from HEAD
-    if file_total_size == 0 && resp.status().is_success() {
-      file_total_size = resp
-        .headers()
-        .get("content-length")
-        .and_then(|v| v.to_str().ok().and_then(|s| s.parse::<u64>().ok()))
-        .unwrap_or(0);
-      self.seen_urls.lock().await.insert(url);
-      *self.total_size.lock().await += file_total_size;
-      total_pb.set_message(human_readable_size(*self.total_size.lock().await));
-    }
-
-    // Open file for writing
-    let mut file =
-      File::options().create(true).append(true).open(&temp_filepath)?;
-
-    // Stream chunks and write to file
-    let mut stream = resp.bytes_stream();
-    while let Some(chunk) = stream.next().await {
-      let chunk = chunk?;
-      let chunk_len = chunk.len();
-      file.write_all(&chunk)?;
-      pb.inc(chunk_len as u64);
+  .build()?;
+  if cli.get_dry_run() {
+    let plan = downloader.plan().await;
+    if cli.get_json() {
+      let json =
+        serde_json::to_string_pretty(&plan).map_err(std::io::Error::other)?;
+      println!("{}", json);
+    } else {
+      print!("{}", plan);
     }
-
-    // Rename temp file to final location
-    fs::rename(&temp_filepath, &filepath)?;
-    pb.finish_with_message(format!(
-      "\x1b[32mOk\x1b[0m \x1b[32m{}\x1b[0m  {} {}",
-      human_readable_size(file_total_size),
-      filename,
-      "✔",
-    ));
-    total_pb.inc(1); // Increment total progress when download completes
-    tokio::time::sleep(tokio::time::Duration::from_millis(rand::random_range(
-      500..1000,
-    )))
-    .await;
-    pb.finish_and_clear();
-
-    Ok(())
+    return Ok(());
   }
+  let c = downloader.clone();
 
-  /// Run the downloader and return Ok(()) on success
-  ///
-  /// Deletes the `self.dest` directory if `self.clean` is true
-  /// Creates the `self.dest` directory if it does not exist
-  ///
-  /// Downloads files concurrently using `self.workers` workers
-  /// Returns Ok(()) on success
-  pub async fn run(self) -> Result<()> {
-    if self.clean {
-      fs::remove_dir_all(&self.dest).unwrap_or(());
-    }
-    fs::create_dir_all(&self.dest)?;
-
-    let mp = Arc::new(MultiProgress::new());
-    mp.set_alignment(MultiProgressAlignment::Top);
-    let total_files = self.urls.len() as u64;
-    let total_pb = mp.add(ProgressBar::new(total_files));
-    let downloader = Arc::new(self.clone());
-
-    // Total progress bar tracking files completed
-    total_pb.set_style(
-      ProgressStyle::default_bar()
-        .template(
-          "Total: [{elapsed_precise}] [{bar:40.green/yellow}] {pos}/{len} \
-           files (Total size: {msg})",
-        )?
-        .progress_chars("#>-"),
-    );
-    total_pb
-      .set_message(human_readable_size(*downloader.total_size.lock().await));
-
-    // Create tasks with worker limit
-    let semaphore = Arc::new(tokio::sync::Semaphore::new(self.workers));
-    let tasks = self
-      .urls
-      .clone()
-      .into_iter()
-      .map(|url| {
-        let mp = mp.clone();
-        let semaphore = semaphore.clone();
-        let total_pb = total_pb.clone();
-        let downloader = downloader.clone();
-        async move {
-          let _permit = semaphore.acquire().await.unwrap();
-          downloader.download_file(url.clone(), mp, total_pb).await.inspect_err(
-            |e| {
-              tracing::error!(
-                "Error downloading file from: {} error: {:?}",
-                url,
-                e
-              )
-            },
-          )
-        }
-      })
-      .collect::<task::JoinSet<_>>();
-
-    // Wait for all downloads
-    let results = tasks.join_all().await;
-    for res in results {
-      if res.is_ok() {};
-    }
-
-    // Finish total progress bar
-    total_pb.finish_with_message(human_readable_size(
-      *downloader.total_size.lock().await,
-    ));
-    Ok(())
+  let summary = match downloader.run().await {
+    Ok(summary) => summary,
+    Err(e) => {
+      tracing::error!("Download failed: {}", e);
+      std::process::exit(e.exit_code());
+    },
+  };
+  if summary.failed > 0 {
+    tracing::error!("{} of {} downloads failed", summary.failed, summary.total);
   }
-}
-
-/// Convert bytes to human-readable format
-pub fn human_readable_size(bytes: u64) -> String {
-  use humansize::{format_size, DECIMAL};
-  format_size(bytes, DECIMAL)
-}
-
-/// Main entry point
-#[tokio::main]
-async fn main() -> Result<()> {
-  use clap::{CommandFactory, Parser};
-  utils::init_tracing();
-  info!("Multi File Downloader v{}", build::PKG_VERSION);
-
-  let mut cmd = Cli::command();
-  let cli = Cli::parse();
-
-  if let Some(shell) = cli.completion {
-    cli::generate_completions("multifiledownloader", shell.as_ref(), &mut cmd);
-    return Ok(());
+  if summary.skipped > 0 {
+    tracing::warn!(
+      "{} of {} downloads were skipped",
+      summary.skipped,
+      summary.total
+    );
   }
-
-  if cli.get_urls().is_empty() {
-    eprintln!("Error: No URLs provided");
-    std::process::exit(1);
+  if summary.failed + summary.skipped == 0 {
+    info!("Download completed successfully");
   }
-
-  let downloader = Downloader::new(
-    cli.get_urls(),
-    cli.get_dest(),
-    cli.get_workers(),
-    cli.get_clean(),
-  );
-  let c = downloader.clone();
-
-  downloader.run().await?;
-  info!("Download completed successfully");
   info!(
-    "Downloaded {} files of size {} to {} using {} workers",
-    c.num_urls(),
+    "Downloaded {} of {} files of size {} to {} using {} workers",
+    summary.succeeded,
+    summary.total,
     c.get_total_size_human().await,
     c.get_dest().display(),
     c.num_workers(),
   );
+  info!(
+    "Retried {} times (429: {}, 5xx: {}, network: {}, timeout: {}, \
+     checksum: {})",
+    summary.retries.total(),
+    summary.retries.rate_limited,
+    summary.retries.server_error,
+    summary.retries.network,
+    summary.retries.timeout,
+    summary.retries.checksum,
+  );
+  if let Some(path) = cli.get_report() {
+    fs::write(&path, summary.render(cli.get_report_format())?)?;
+    info!("Wrote report to {}", path.display());
+  }
+  if cli.get_json() {
+    println!("{}", summary.render(ReportFormat::Json)?);
+  }
+  let code = summary.exit_code();
+  if code != summary::EXIT_SUCCESS {
+    std::process::exit(code);
+  }
   Ok(())
 }
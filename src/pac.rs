@@ -0,0 +1,171 @@
+use std::{
+  collections::HashMap,
+  net::{IpAddr, ToSocketAddrs, UdpSocket},
+  sync::{Arc, Mutex},
+};
+
+use boa_engine::{
+  js_string,
+  Context,
+  JsArgs,
+  JsResult,
+  JsString,
+  JsValue,
+  NativeFunction,
+  Source,
+};
+use url::Url;
+
+use crate::error::{DownloadError, Result};
+
+/// JavaScript implementations of the standard PAC helper functions.
+///
+/// `weekdayRange`, `dateRange` and `timeRange` always match.
+const PAC_PRELUDE: &str = r#"
+function isPlainHostName(host) { return host.indexOf('.') < 0; }
+function dnsDomainIs(host, domain) {
+  return host.length >= domain.length &&
+    host.substring(host.length - domain.length) == domain;
+}
+function localHostOrDomainIs(host, hostdom) {
+  return host == hostdom || hostdom.lastIndexOf(host + '.', 0) == 0;
+}
+function isResolvable(host) { return dnsResolve(host) != null; }
+function convert_addr(ip) {
+  var b = ip.split('.');
+  return ((b[0] & 0xff) << 24) | ((b[1] & 0xff) << 16) |
+    ((b[2] & 0xff) << 8) | (b[3] & 0xff);
+}
+function isInNet(host, pattern, mask) {
+  var ip = /^\d+\.\d+\.\d+\.\d+$/.test(host) ? host : dnsResolve(host);
+  if (ip == null) return false;
+  var m = convert_addr(mask);
+  return (convert_addr(ip) & m) == (convert_addr(pattern) & m);
+}
+function dnsDomainLevels(host) { return host.split('.').length - 1; }
+function shExpMatch(str, pattern) {
+  var re = pattern.replace(/[.+^${}()|[\]\\]/g, '\\$&')
+    .replace(/\*/g, '.*').replace(/\?/g, '.');
+  return new RegExp('^' + re + '$').test(str);
+}
+function weekdayRange() { return true; }
+function dateRange() { return true; }
+function timeRange() { return true; }
+"#;
+
+/// Resolves the proxy to use for each URL by evaluating a proxy
+/// auto-configuration (PAC) script's `FindProxyForURL(url, host)`.
+///
+/// Results are cached per scheme and host.
+#[derive(Debug)]
+pub struct PacResolver {
+  script: Arc<str>,
+  cache:  Mutex<HashMap<String, Option<Url>>>,
+}
+
+impl PacResolver {
+  /// Create a resolver for `script`, checking that it evaluates and defines
+  /// `FindProxyForURL`
+  pub fn new(script: String) -> Result<Self> {
+    evaluate(&script, "http://example.com/", "example.com").map_err(|e| {
+      DownloadError::ProxyError(format!("Invalid PAC script: {}", e))
+    })?;
+    Ok(Self {
+      script: script.into(),
+      cache:  Default::default(),
+    })
+  }
+
+  /// Proxy to use for `url`, `None` to connect directly
+  pub fn find_proxy(&self, url: &Url) -> Option<Url> {
+    let host = url.host_str().unwrap_or_default();
+    let key = format!("{}://{}", url.scheme(), host);
+    if let Some(proxy) = self.cache.lock().ok()?.get(&key) {
+      return proxy.clone();
+    }
+    let proxy = match evaluate(&self.script, url.as_str(), host) {
+      Ok(result) => parse_pac_result(&result),
+      Err(e) => {
+        tracing::warn!(
+          "PAC evaluation failed for {}, going direct: {}",
+          url,
+          e
+        );
+        None
+      },
+    };
+    self.cache.lock().ok()?.insert(key, proxy.clone());
+    proxy
+  }
+}
+
+/// Turn a PAC result like `PROXY proxy:8080; DIRECT` into the first proxy URL,
+/// `None` when the first entry is `DIRECT` or unrecognized
+pub fn parse_pac_result(result: &str) -> Option<Url> {
+  let entry = result.split(';').map(str::trim).find(|e| !e.is_empty())?;
+  let (kind, addr) = entry.split_once(char::is_whitespace)?;
+  let scheme = match kind.to_uppercase().as_str() {
+    "PROXY" | "HTTP" => "http",
+    "HTTPS" => "https",
+    "SOCKS" | "SOCKS5" => "socks5",
+    "SOCKS4" => "socks4",
+    _ => return None,
+  };
+  Url::parse(&format!("{}://{}", scheme, addr.trim())).ok()
+}
+
+fn evaluate(script: &str, url: &str, host: &str) -> JsResult<String> {
+  let mut context = Context::default();
+  context.register_global_builtin_callable(
+    js_string!("dnsResolve"),
+    1,
+    NativeFunction::from_fn_ptr(dns_resolve),
+  )?;
+  context.register_global_builtin_callable(
+    js_string!("myIpAddress"),
+    0,
+    NativeFunction::from_fn_ptr(my_ip_address),
+  )?;
+  context.eval(Source::from_bytes(PAC_PRELUDE))?;
+  context.eval(Source::from_bytes(script))?;
+  let find_proxy =
+    context.global_object().get(js_string!("FindProxyForURL"), &mut context)?;
+  let find_proxy = find_proxy.as_callable().ok_or_else(|| {
+    boa_engine::JsNativeError::typ()
+      .with_message("FindProxyForURL is not a function")
+  })?;
+  let result = find_proxy.call(
+    &JsValue::undefined(),
+    &[JsString::from(url).into(), JsString::from(host).into()],
+    &mut context,
+  )?;
+  Ok(result.to_string(&mut context)?.to_std_string_escaped())
+}
+
+fn dns_resolve(
+  _this: &JsValue,
+  args: &[JsValue],
+  context: &mut Context,
+) -> JsResult<JsValue> {
+  let host = args.get_or_undefined(0).to_string(context)?;
+  let ip = (host.to_std_string_escaped().as_str(), 0)
+    .to_socket_addrs()
+    .ok()
+    .and_then(|mut addrs| addrs.find(|a| a.is_ipv4()))
+    .map(|a| a.ip().to_string());
+  Ok(ip.map(|ip| JsString::from(ip.as_str()).into()).unwrap_or(JsValue::null()))
+}
+
+fn my_ip_address(
+  _this: &JsValue,
+  _args: &[JsValue],
+  _context: &mut Context,
+) -> JsResult<JsValue> {
+  // Connecting a UDP socket sends nothing but picks the outbound interface
+  let ip = UdpSocket::bind("0.0.0.0:0")
+    .and_then(|s| s.connect("192.0.2.1:80").map(|_| s))
+    .and_then(|s| s.local_addr())
+    .map(|a| a.ip())
+    .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+  Ok(JsString::from(ip.to_string().as_str()).into())
+}
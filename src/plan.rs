@@ -0,0 +1,123 @@
+use humansize::{format_size, DECIMAL};
+use serde::Serialize;
+
+/// What a run would do with a URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlannedAction {
+  /// The file would be downloaded
+  Download,
+  /// The file already exists and would be skipped
+  Skip,
+  /// A partial download exists and would be resumed
+  Resume,
+  /// The HEAD request failed, the download would likely fail too
+  Unresolved,
+}
+
+/// A URL in a [`Plan`]
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanEntry {
+  pub url:       String,
+  /// Local filename the URL resolves to
+  pub filename:  String,
+  /// Size in bytes, remote for downloads and local for skips
+  pub size:      Option<u64>,
+  /// Bytes already downloaded, for resumes
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub partial:   Option<u64>,
+  pub action:    PlannedAction,
+  /// Locations the URL redirected through, in order, when traced
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub redirects: Vec<String>,
+  /// URL the redirects end at, when traced
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub final_url: Option<String>,
+  /// Why the URL could not be resolved
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error:     Option<String>,
+}
+
+/// What a [`crate::Downloader::run`] would do, built without writing
+/// anything to disk
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+  /// Always `"planned"`, distinguishes plans from run summaries
+  pub status:      &'static str,
+  /// Number of URLs scheduled
+  pub total:       usize,
+  /// URLs that would be downloaded
+  pub download:    usize,
+  /// URLs that would be skipped
+  pub skip:        usize,
+  /// URLs whose partial download would be resumed
+  pub resume:      usize,
+  /// URLs whose HEAD request failed
+  pub unresolved:  usize,
+  /// Projected number of bytes to download, partials already downloaded
+  /// excluded
+  pub total_bytes: u64,
+  pub entries:     Vec<PlanEntry>,
+}
+
+impl Plan {
+  /// Build a plan from its entries, computing the totals
+  pub fn new(entries: Vec<PlanEntry>) -> Self {
+    let count = |action| entries.iter().filter(|e| e.action == action).count();
+    Plan {
+      status: "planned",
+      total: entries.len(),
+      download: count(PlannedAction::Download),
+      skip: count(PlannedAction::Skip),
+      resume: count(PlannedAction::Resume),
+      unresolved: count(PlannedAction::Unresolved),
+      total_bytes: entries
+        .iter()
+        .map(|e| match e.action {
+          PlannedAction::Download => e.size.unwrap_or(0),
+          PlannedAction::Resume => {
+            e.size.unwrap_or(0).saturating_sub(e.partial.unwrap_or(0))
+          },
+          _ => 0,
+        })
+        .sum(),
+      entries,
+    }
+  }
+}
+
+/// One line per URL and a closing total, for people rather than scripts
+impl std::fmt::Display for Plan {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let size = |bytes: Option<u64>| {
+      bytes.map_or("?".to_string(), |b| format_size(b, DECIMAL))
+    };
+    for entry in &self.entries {
+      let action = match entry.action {
+        PlannedAction::Download => "download",
+        PlannedAction::Skip => "skip",
+        PlannedAction::Resume => "resume",
+        PlannedAction::Unresolved => "unresolved",
+      };
+      write!(f, "{:<10} {:>10}  {}", action, size(entry.size), entry.filename)?;
+      if let Some(partial) = entry.partial {
+        write!(f, " (from {})", size(Some(partial)))?;
+      }
+      if let Some(error) = &entry.error {
+        write!(f, " ({})", error)?;
+      }
+      writeln!(f)?;
+    }
+    writeln!(
+      f,
+      "{} URLs: {} to download, {} to resume, {} to skip, {} unresolved; {} \
+       to download",
+      self.total,
+      self.download,
+      self.resume,
+      self.skip,
+      self.unresolved,
+      size(Some(self.total_bytes))
+    )
+  }
+}
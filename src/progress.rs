@@ -0,0 +1,73 @@
+//! Progress bar templates sized to the terminal, and progress events for
+//! frontends rendering their own.
+//!
+//! Templates are computed whenever a bar is created, so a resized terminal
+//! is picked up by every bar created afterwards.
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::report::DownloadOutcome;
+
+/// Terminal width below which the compact templates are used
+const COMPACT_WIDTH: usize = 60;
+/// Default bar width on wide terminals
+const MAX_BAR_WIDTH: usize = 40;
+
+/// Current terminal width in columns, 80 when it cannot be determined
+fn terminal_width() -> usize {
+  terminal_size::terminal_size().map(|(w, _)| w.0 as usize).unwrap_or(80)
+}
+
+/// Template for per-file progress bars
+pub fn file_template() -> String {
+  let width = terminal_width();
+  if width < COMPACT_WIDTH {
+    let bar = width.saturating_sub(30).clamp(5, 20);
+    return format!("{{bar:{}.cyan/blue}} {{percent:>3}}% {{msg}}", bar);
+  }
+  let bar = width.saturating_sub(70).clamp(10, MAX_BAR_WIDTH);
+  format!(
+    "[{{elapsed_precise}}] [{{bar:{}.cyan/blue}}] {{bytes}}/{{total_bytes}} \
+     ({{eta}}) {{msg}}",
+    bar
+  )
+}
+
+/// Template for the total progress bar
+pub fn total_template() -> String {
+  let width = terminal_width();
+  if width < COMPACT_WIDTH {
+    let bar = width.saturating_sub(30).clamp(5, 20);
+    return format!(
+      "Total {{bar:{}.green/yellow}} {{pos}}/{{len}} {{msg}}",
+      bar
+    );
+  }
+  let bar = width.saturating_sub(60).clamp(10, MAX_BAR_WIDTH);
+  format!(
+    "Total: [{{elapsed_precise}}] [{{bar:{}.green/yellow}}] {{pos}}/{{len}} \
+     files (Total size: {{msg}})",
+    bar
+  )
+}
+
+/// Progress of a download, sent by [`crate::Downloader::run_with_events`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+  /// The transfer of `url` started, `offset` bytes were already downloaded
+  Started {
+    url:    String,
+    /// Size of the file, when the server reports it
+    total:  Option<u64>,
+    offset: u64,
+  },
+  /// `bytes` more bytes of `url` were written to disk
+  Progress { url: String, bytes: u64 },
+  /// `url` is done, downloaded or skipped
+  Finished { url: String, path: PathBuf, outcome: DownloadOutcome },
+  /// `url` failed, after any retries
+  Error { url: String, error: String },
+}
@@ -0,0 +1,70 @@
+use std::{
+  collections::BTreeMap,
+  path::{Path, PathBuf},
+};
+
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+
+use crate::report::redacted_headers;
+
+/// Where a downloaded file came from, written next to it as
+/// `<file>.meta.json` when `--provenance` is enabled
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+  /// URL as requested
+  pub source_url: String,
+  /// URL the content was served from, after redirects
+  pub final_url:  String,
+  /// RFC 3339 UTC time the download completed
+  pub timestamp:  String,
+  /// Size of the file in bytes
+  pub size:       u64,
+  /// Hex encoded SHA-256 digest of the file
+  pub sha256:     String,
+  /// `ETag` response header, if any
+  pub etag:       Option<String>,
+  /// All response headers, with sensitive values redacted
+  pub headers:    BTreeMap<String, String>,
+}
+
+impl Provenance {
+  /// Record the provenance of the downloaded file at `path`, hashing it unless
+  /// its SHA-256 is already known
+  pub fn new(
+    source_url: &str,
+    final_url: &str,
+    headers: &HeaderMap,
+    path: &Path,
+    sha256: Option<String>,
+  ) -> std::io::Result<Self> {
+    let headers = redacted_headers(headers);
+    Ok(Self {
+      source_url: source_url.to_string(),
+      final_url: final_url.to_string(),
+      timestamp: time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default(),
+      size: path.metadata()?.len(),
+      sha256: match sha256 {
+        Some(sha256) => sha256,
+        None => crate::checksum::sha256_file(path)?,
+      },
+      etag: headers.get("etag").cloned(),
+      headers,
+    })
+  }
+
+  /// Path of the sidecar file for the download at `path`
+  pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".meta.json");
+    path.with_file_name(name)
+  }
+
+  /// Write this record as pretty JSON to the sidecar of `path`
+  pub fn write_sidecar(&self, path: &Path) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(self)?;
+    std::fs::write(Self::sidecar_path(path), json)
+  }
+}
@@ -0,0 +1,254 @@
+use std::{
+  str::FromStr,
+  sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+    OnceLock,
+  },
+};
+
+use time::{OffsetDateTime, UtcOffset};
+use tokio::time::{Duration, Instant};
+
+/// Local UTC offset used by [`RateSchedule`], see [`init_local_offset`]
+static LOCAL_OFFSET: OnceLock<UtcOffset> = OnceLock::new();
+
+/// Look up the local UTC offset for rate schedules.
+///
+/// `time` can only read it while the process is single threaded, so call
+/// this before starting the async runtime. Falls back to UTC when the offset
+/// cannot be determined.
+pub fn init_local_offset() -> UtcOffset {
+  *LOCAL_OFFSET.get_or_init(|| {
+    UtcOffset::current_local_offset().unwrap_or_else(|_| {
+      tracing::warn!("Cannot determine the local timezone, using UTC");
+      UtcOffset::UTC
+    })
+  })
+}
+
+/// Limits the aggregate throughput of all downloads, sharing it equally
+/// between the downloads that are streaming.
+///
+/// Each download streams through a [`RateShare`], a token bucket refilled at
+/// the rate divided by the number of live shares, so a fast stream cannot
+/// starve the others. A limiter without a rate lets everything through. The
+/// rate can be changed while downloads are running.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+  /// Bytes per second, 0 for unlimited
+  rate:   AtomicU64,
+  /// Number of live shares
+  active: AtomicUsize,
+}
+
+impl RateLimiter {
+  /// Create a limiter allowing `rate` bytes per second
+  pub fn new(rate: Option<u64>) -> Self {
+    Self {
+      rate:   AtomicU64::new(rate.unwrap_or(0)),
+      active: AtomicUsize::new(0),
+    }
+  }
+
+  /// Change the allowed rate in bytes per second, `None` for unlimited
+  pub fn set_rate(&self, rate: Option<u64>) {
+    self.rate.store(rate.unwrap_or(0), Ordering::Relaxed);
+  }
+
+  /// Take a share of the rate for one download, released when dropped
+  pub fn share(self: &Arc<Self>) -> RateShare {
+    self.active.fetch_add(1, Ordering::Relaxed);
+    RateShare {
+      limiter: self.clone(),
+      tokens:  0.0,
+      last:    Instant::now(),
+    }
+  }
+}
+
+/// One download's equal share of a [`RateLimiter`]
+#[derive(Debug)]
+pub struct RateShare {
+  limiter: Arc<RateLimiter>,
+  /// Available bytes, negative while paying off a debt
+  tokens:  f64,
+  last:    Instant,
+}
+
+impl RateShare {
+  /// Wait until `bytes` may be consumed under this share of the rate
+  pub async fn acquire(&mut self, bytes: u64) {
+    let rate = self.limiter.rate.load(Ordering::Relaxed);
+    let now = Instant::now();
+    let elapsed = now.duration_since(self.last).as_secs_f64();
+    self.last = now;
+    if rate == 0 {
+      self.tokens = 0.0;
+      return;
+    }
+    let active = self.limiter.active.load(Ordering::Relaxed).max(1);
+    let rate = rate as f64 / active as f64;
+    // Allow bursts of up to one second worth of bytes
+    self.tokens = (self.tokens + elapsed * rate).min(rate);
+    self.tokens -= bytes as f64;
+    if self.tokens < 0.0 {
+      tokio::time::sleep(Duration::from_secs_f64(-self.tokens / rate)).await;
+    }
+  }
+}
+
+impl Drop for RateShare {
+  fn drop(&mut self) {
+    self.limiter.active.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+/// A time-of-day window with its own rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateWindow {
+  /// Start of the window in minutes since midnight, inclusive
+  pub start: u32,
+  /// End of the window in minutes since midnight, exclusive. Windows with
+  /// `end <= start` wrap around midnight.
+  pub end:   u32,
+  /// Bytes per second allowed inside the window
+  pub rate:  u64,
+}
+
+impl RateWindow {
+  fn contains(&self, minute: u32) -> bool {
+    if self.start < self.end {
+      (self.start..self.end).contains(&minute)
+    } else {
+      minute >= self.start || minute < self.end
+    }
+  }
+}
+
+/// Time-of-day bandwidth schedule, e.g. `00:00-06:00=10M,06:00-18:00=1M`.
+///
+/// Times are interpreted in the local timezone of the machine running the
+/// downloader, as of [`init_local_offset`], so a daylight saving change
+/// during a run is not picked up. Outside of every window downloads are
+/// unlimited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateSchedule {
+  pub windows: Vec<RateWindow>,
+}
+
+impl RateSchedule {
+  /// Rate in bytes per second at `minute` minutes since midnight, the first
+  /// matching window wins
+  pub fn rate_at(&self, minute: u32) -> Option<u64> {
+    self.windows.iter().find(|w| w.contains(minute)).map(|w| w.rate)
+  }
+
+  /// Rate in bytes per second at the current local time
+  pub fn current_rate(&self) -> Option<u64> {
+    let now = OffsetDateTime::now_utc().to_offset(init_local_offset());
+    self.rate_at(now.hour() as u32 * 60 + now.minute() as u32)
+  }
+}
+
+fn parse_time_of_day(s: &str) -> Result<u32, String> {
+  let (hour, minute) =
+    s.trim().split_once(':').ok_or_else(|| format!("Invalid time: {}", s))?;
+  let hour: u32 = hour.parse().map_err(|_| format!("Invalid hour: {}", s))?;
+  let minute: u32 =
+    minute.parse().map_err(|_| format!("Invalid minute: {}", s))?;
+  if hour > 24 || minute > 59 || (hour == 24 && minute != 0) {
+    return Err(format!("Invalid time: {}", s));
+  }
+  Ok(hour * 60 + minute)
+}
+
+impl FromStr for RateSchedule {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let windows = s
+      .split(',')
+      .map(str::trim)
+      .filter(|w| !w.is_empty())
+      .map(|w| {
+        let (range, rate) = w
+          .split_once('=')
+          .ok_or_else(|| format!("Expected START-END=RATE, got: {}", w))?;
+        let (start, end) = range
+          .split_once('-')
+          .ok_or_else(|| format!("Expected START-END, got: {}", range))?;
+        Ok(RateWindow {
+          start: parse_time_of_day(start)?,
+          end:   parse_time_of_day(end)? % (24 * 60),
+          rate:  crate::utils::parse_size(rate)?,
+        })
+      })
+      .collect::<Result<Vec<_>, String>>()?;
+    if windows.is_empty() {
+      return Err("Empty rate schedule".into());
+    }
+    Ok(Self {
+      windows,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Bytes `share` gets through in `window`, acquiring `chunk` at a time
+  async fn stream(mut share: RateShare, chunk: u64, window: Duration) -> u64 {
+    let deadline = Instant::now() + window;
+    let mut bytes = 0;
+    while Instant::now() < deadline {
+      share.acquire(chunk).await;
+      bytes += chunk;
+    }
+    bytes
+  }
+
+  #[tokio::test]
+  async fn concurrent_shares_get_equal_throughput() {
+    let rate = 100_000;
+    let limiter = Arc::new(RateLimiter::new(Some(rate)));
+    let window = Duration::from_secs(1);
+    // A stream asking for large chunks does not starve one asking for small
+    let (small, large) = tokio::join!(
+      tokio::spawn(stream(limiter.share(), 1_000, window)),
+      tokio::spawn(stream(limiter.share(), 10_000, window)),
+    );
+    let (small, large) = (small.unwrap(), large.unwrap());
+    for bytes in [small, large] {
+      assert!(
+        (40_000..=62_000).contains(&bytes),
+        "{} bytes out of {} for two streams",
+        bytes,
+        rate
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn dropped_shares_release_their_part() {
+    let limiter = Arc::new(RateLimiter::new(Some(1_000)));
+    let first = limiter.share();
+    let second = limiter.share();
+    assert_eq!(limiter.active.load(Ordering::Relaxed), 2);
+    drop(first);
+    drop(second);
+    assert_eq!(limiter.active.load(Ordering::Relaxed), 0);
+  }
+
+  #[tokio::test]
+  async fn unlimited_shares_never_wait() {
+    let limiter = Arc::new(RateLimiter::new(None));
+    let start = Instant::now();
+    let mut share = limiter.share();
+    for _ in 0..1000 {
+      share.acquire(1 << 20).await;
+    }
+    assert!(start.elapsed() < Duration::from_millis(100));
+  }
+}
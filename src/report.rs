@@ -0,0 +1,171 @@
+use std::{
+  collections::BTreeMap,
+  fs::File,
+  io::Write,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
+
+use reqwest::header::HeaderMap;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// How the download of a URL ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadOutcome {
+  /// Downloaded from scratch
+  Downloaded,
+  /// Completed from a partial download
+  Resumed,
+  /// Not downloaded: already present, unmodified, identical to another
+  /// download or its host was down
+  Skipped,
+  /// Only in results, download errors are returned as `Err`
+  Failed,
+}
+
+/// Outcome of a single URL of a run
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DownloadResult {
+  pub url:          String,
+  /// Local filename the URL resolved to
+  pub filename:     String,
+  pub status:       DownloadOutcome,
+  /// Size of the file on disk after a successful download
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub bytes:        Option<u64>,
+  /// Time spent on the URL, waiting for a worker excluded
+  pub elapsed_secs: f64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error:        Option<String>,
+  /// Last response received for the URL, with
+  /// `--include-headers-in-report`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub response:     Option<ResponseRecord>,
+}
+
+/// Headers whose values are replaced by [`REDACTED`] in a [`ResponseRecord`]
+/// and in provenance sidecars
+pub const SENSITIVE_HEADERS: &[&str] = &[
+  "authorization",
+  "cookie",
+  "proxy-authenticate",
+  "proxy-authorization",
+  "set-cookie",
+  "www-authenticate",
+];
+
+/// Stand-in for the value of a sensitive header
+pub const REDACTED: &str = "[redacted]";
+
+/// Status and headers of a response, with sensitive values redacted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct ResponseRecord {
+  pub status:  u16,
+  /// Header values by lowercase name, repeated headers joined with `, `
+  pub headers: BTreeMap<String, String>,
+}
+
+impl ResponseRecord {
+  pub fn new(status: u16, headers: &HeaderMap) -> Self {
+    ResponseRecord {
+      status,
+      headers: redacted_headers(headers),
+    }
+  }
+}
+
+/// Header values of `headers` by lowercase name, with [`SENSITIVE_HEADERS`]
+/// redacted and repeated headers joined with `, `
+pub fn redacted_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+  let mut record = BTreeMap::<String, String>::new();
+  for (name, value) in headers {
+    let value = if SENSITIVE_HEADERS.contains(&name.as_str()) {
+      REDACTED.into()
+    } else {
+      String::from_utf8_lossy(value.as_bytes()).into_owned()
+    };
+    record
+      .entry(name.to_string())
+      .and_modify(|v| {
+        if v != REDACTED {
+          v.push_str(", ");
+          v.push_str(&value);
+        }
+      })
+      .or_insert(value);
+  }
+  record
+}
+
+/// A finished download, passed to the callback set with
+/// [`crate::Downloader::with_on_complete`]
+#[derive(Debug, Clone)]
+pub struct DownloadEvent {
+  pub url:    String,
+  /// Local path of the file, where it would have been saved on failure
+  pub path:   PathBuf,
+  /// Size of the file on disk after a successful download
+  pub bytes:  Option<u64>,
+  pub status: DownloadOutcome,
+  pub error:  Option<String>,
+}
+
+/// Callback invoked with each finished download
+pub type OnComplete = Arc<dyn Fn(&DownloadEvent) + Send + Sync>;
+
+/// JSON-lines file each [`DownloadResult`] is appended to as soon as it is
+/// known, so the outcomes of a crashed run survive
+#[derive(Debug, Clone)]
+pub struct ResultsLog {
+  file: Arc<Mutex<File>>,
+}
+
+impl ResultsLog {
+  /// Open `path` for appending, creating it if needed
+  pub fn open(path: &Path) -> std::io::Result<Self> {
+    let file = File::options().create(true).append(true).open(path)?;
+    Ok(ResultsLog {
+      file: Arc::new(Mutex::new(file)),
+    })
+  }
+
+  /// Append `result` as one line and flush it.
+  ///
+  /// Failures are logged and never fail the download.
+  pub fn append(&self, result: &DownloadResult) {
+    let written = serde_json::to_string(result)
+      .map_err(std::io::Error::other)
+      .and_then(|line| {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", line)?;
+        file.flush()
+      });
+    if let Err(e) = written {
+      tracing::warn!("Failed to append the result of {}: {}", result.url, e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use reqwest::header::{HeaderValue, SET_COOKIE};
+
+  use super::*;
+
+  #[test]
+  fn response_record_redacts_sensitive_headers() {
+    let mut headers = HeaderMap::new();
+    headers.insert("content-type", HeaderValue::from_static("text/plain"));
+    headers.append(SET_COOKIE, HeaderValue::from_static("a=1"));
+    headers.append(SET_COOKIE, HeaderValue::from_static("b=2"));
+    headers.append("vary", HeaderValue::from_static("accept"));
+    headers.append("vary", HeaderValue::from_static("origin"));
+    let record = ResponseRecord::new(200, &headers);
+    assert_eq!(record.status, 200);
+    assert_eq!(record.headers["content-type"], "text/plain");
+    assert_eq!(record.headers["set-cookie"], REDACTED);
+    assert_eq!(record.headers["vary"], "accept, origin");
+  }
+}
@@ -0,0 +1,35 @@
+use std::io::IsTerminal;
+
+use tracing::warn;
+
+use crate::{error::Result, human_readable_size, Downloader};
+
+/// Let the user pick which URLs of `downloader` to download from a terminal
+/// multi-select listing each file with its size from a HEAD pre-pass.
+///
+/// When stdin is not a terminal the prompt is skipped and every URL is kept.
+pub async fn interactive_select(downloader: Downloader) -> Result<Downloader> {
+  if !std::io::stdin().is_terminal() {
+    warn!("stdin is not a terminal, skipping --interactive-select");
+    return Ok(downloader);
+  }
+  let sizes = downloader.presize().await;
+  let items = sizes
+    .iter()
+    .map(|(url, size)| {
+      format!(
+        "{}  ({})  {}",
+        downloader.local_filename(url),
+        size.map(human_readable_size).unwrap_or_else(|| "unknown size".into()),
+        url
+      )
+    })
+    .collect::<Vec<_>>();
+  let chosen = dialoguer::MultiSelect::new()
+    .with_prompt("Select files to download (space to toggle, enter to confirm)")
+    .items(&items)
+    .interact()
+    .map_err(std::io::Error::other)?;
+  let urls = chosen.into_iter().map(|i| sizes[i].0.clone()).collect();
+  Ok(downloader.with_urls(urls))
+}
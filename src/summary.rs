@@ -0,0 +1,314 @@
+use std::{
+  collections::BTreeMap,
+  sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+  cli::ReportFormat,
+  report::{DownloadOutcome, DownloadResult},
+};
+
+/// Exit code of a successful run
+pub const EXIT_SUCCESS: i32 = 0;
+/// Exit code when the run itself failed, e.g. the destination could not be
+/// created
+pub const EXIT_ERROR: i32 = 1;
+/// Exit code when some downloads failed
+pub const EXIT_PARTIAL_FAILURE: i32 = 2;
+/// Exit code when every download failed
+pub const EXIT_ALL_FAILED: i32 = 3;
+/// Exit code when the run was aborted for making no progress within
+/// `--max-idle-time`
+pub const EXIT_DEADLINE: i32 = 4;
+/// Exit code when the disk filled up
+pub const EXIT_DISK_FULL: i32 = 5;
+
+/// Aggregate outcome of a [`crate::Downloader::run`]
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub struct RunSummary {
+  /// Number of URLs scheduled
+  pub total:               usize,
+  /// Downloads that completed (or were already present)
+  pub succeeded:           usize,
+  /// Files downloaded from scratch
+  pub downloaded:          usize,
+  /// Files completed from a partial download
+  pub resumed:             usize,
+  /// Files kept as they were already present, unmodified or identical to
+  /// another download
+  pub unchanged:           usize,
+  /// Downloads that failed
+  pub failed:              usize,
+  /// Downloads skipped because their host was down
+  pub skipped:             usize,
+  /// Downloads cancelled after a failure with `--fail-fast`
+  pub aborted:             usize,
+  /// Whether any download failed because the disk was full
+  pub disk_full:           bool,
+  /// Archives that downloaded fine but could not be extracted
+  pub extraction_failures: usize,
+  /// Number of 429 responses per host
+  pub throttled:           BTreeMap<String, usize>,
+  /// Retries made, by reason
+  pub retries:             RetryCounts,
+  /// Outcome of each URL, in the order they finished
+  pub results:             Vec<DownloadResult>,
+}
+
+impl RunSummary {
+  /// JSON Schema of the run report, for tools validating it or generating
+  /// types from it
+  pub fn json_schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(RunSummary)
+  }
+
+  /// Serialize this summary as a report in `format`
+  pub fn render(&self, format: ReportFormat) -> std::io::Result<String> {
+    match format {
+      ReportFormat::Json => {
+        serde_json::to_string_pretty(self).map_err(std::io::Error::other)
+      },
+      ReportFormat::Toml => {
+        toml::to_string_pretty(self).map_err(std::io::Error::other)
+      },
+      ReportFormat::Csv => {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for result in &self.results {
+          writer.serialize(CsvRow::from(result))?;
+        }
+        String::from_utf8(writer.into_inner().map_err(|e| e.into_error())?)
+          .map_err(std::io::Error::other)
+      },
+    }
+  }
+
+  /// Process exit code describing this run
+  pub fn exit_code(&self) -> i32 {
+    let unsuccessful = self.failed + self.skipped + self.aborted;
+    if self.disk_full {
+      EXIT_DISK_FULL
+    } else if unsuccessful == 0 {
+      EXIT_SUCCESS
+    } else if unsuccessful >= self.total {
+      EXIT_ALL_FAILED
+    } else {
+      EXIT_PARTIAL_FAILURE
+    }
+  }
+}
+
+/// A [`DownloadResult`] flattened into a CSV row
+#[derive(Serialize)]
+struct CsvRow<'a> {
+  url:          &'a str,
+  filename:     &'a str,
+  status:       DownloadOutcome,
+  bytes:        Option<u64>,
+  elapsed_secs: f64,
+  http_status:  Option<u16>,
+  error:        Option<&'a str>,
+}
+
+impl<'a> From<&'a DownloadResult> for CsvRow<'a> {
+  fn from(result: &'a DownloadResult) -> Self {
+    CsvRow {
+      url:          &result.url,
+      filename:     &result.filename,
+      status:       result.status,
+      bytes:        result.bytes,
+      elapsed_secs: result.elapsed_secs,
+      http_status:  result.response.as_ref().map(|r| r.status),
+      error:        result.error.as_deref(),
+    }
+  }
+}
+
+/// Why a request was retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryReason {
+  /// The server answered 429 Too Many Requests
+  RateLimited,
+  /// The server answered with a 5xx status
+  ServerError,
+  /// The connection failed or the body was cut short
+  Network,
+  /// The request timed out, counted against `--timeout-retries`
+  Timeout,
+  /// The download completed but failed checksum verification
+  Checksum,
+}
+
+/// Retries made during a run, by reason
+#[derive(Debug, Clone, Copy, Default, Serialize, JsonSchema)]
+pub struct RetryCounts {
+  pub rate_limited: usize,
+  pub server_error: usize,
+  pub network:      usize,
+  pub timeout:      usize,
+  pub checksum:     usize,
+}
+
+impl RetryCounts {
+  /// Retries for any reason
+  pub fn total(&self) -> usize {
+    self.rate_limited
+      + self.server_error
+      + self.network
+      + self.timeout
+      + self.checksum
+  }
+}
+
+/// Retry counters shared by all downloads of a run
+#[derive(Debug, Default)]
+pub struct RetryCounters {
+  rate_limited: AtomicUsize,
+  server_error: AtomicUsize,
+  network:      AtomicUsize,
+  timeout:      AtomicUsize,
+  checksum:     AtomicUsize,
+}
+
+impl RetryCounters {
+  /// Count a retry for `reason`
+  pub fn record(&self, reason: RetryReason) {
+    let counter = match reason {
+      RetryReason::RateLimited => &self.rate_limited,
+      RetryReason::ServerError => &self.server_error,
+      RetryReason::Network => &self.network,
+      RetryReason::Timeout => &self.timeout,
+      RetryReason::Checksum => &self.checksum,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Current counts
+  pub fn snapshot(&self) -> RetryCounts {
+    RetryCounts {
+      rate_limited: self.rate_limited.load(Ordering::Relaxed),
+      server_error: self.server_error.load(Ordering::Relaxed),
+      network:      self.network.load(Ordering::Relaxed),
+      timeout:      self.timeout.load(Ordering::Relaxed),
+      checksum:     self.checksum.load(Ordering::Relaxed),
+    }
+  }
+}
+
+/// Live counters of a run, reported by the periodic health log
+#[derive(Debug, Default)]
+pub struct RunCounters {
+  /// Bytes written to disk
+  pub bytes:     AtomicU64,
+  /// Downloads holding a worker
+  pub active:    AtomicUsize,
+  /// Downloads finished, successfully or not
+  pub completed: AtomicUsize,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{error::DownloadError, report::ResponseRecord};
+
+  #[test]
+  fn exit_codes_follow_failure_classes() {
+    let summary = |failed, disk_full| RunSummary {
+      total: 2,
+      succeeded: 2 - failed,
+      failed,
+      disk_full,
+      ..Default::default()
+    };
+    assert_eq!(summary(0, false).exit_code(), EXIT_SUCCESS);
+    assert_eq!(summary(1, false).exit_code(), EXIT_PARTIAL_FAILURE);
+    assert_eq!(summary(2, false).exit_code(), EXIT_ALL_FAILED);
+    assert_eq!(summary(1, true).exit_code(), EXIT_DISK_FULL);
+  }
+
+  #[test]
+  fn run_errors_map_to_exit_codes() {
+    let idle = DownloadError::IdleTimeout(std::time::Duration::from_secs(1));
+    assert_eq!(idle.exit_code(), EXIT_DEADLINE);
+    let full = DownloadError::IoError(std::io::ErrorKind::StorageFull.into());
+    assert_eq!(full.exit_code(), EXIT_DISK_FULL);
+    let other = DownloadError::ConfigError("workers".into());
+    assert_eq!(other.exit_code(), EXIT_ERROR);
+  }
+
+  fn sample() -> RunSummary {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("etag", "\"abc\"".parse().unwrap());
+    RunSummary {
+      total: 2,
+      succeeded: 1,
+      downloaded: 1,
+      failed: 1,
+      throttled: BTreeMap::from([("example.com".to_string(), 3)]),
+      retries: RetryCounts {
+        timeout: 2,
+        ..Default::default()
+      },
+      results: vec![
+        DownloadResult {
+          url:          "https://example.com/a.bin".into(),
+          filename:     "a.bin".into(),
+          status:       DownloadOutcome::Downloaded,
+          bytes:        Some(10),
+          elapsed_secs: 0.5,
+          error:        None,
+          response:     Some(ResponseRecord::new(200, &headers)),
+        },
+        DownloadResult {
+          url:          "https://example.com/b,c".into(),
+          filename:     "b,c".into(),
+          status:       DownloadOutcome::Failed,
+          bytes:        None,
+          elapsed_secs: 1.0,
+          error:        Some("404 Not Found".into()),
+          response:     None,
+        },
+      ],
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn csv_report_has_one_row_per_url() {
+    let csv = sample().render(ReportFormat::Csv).unwrap();
+    assert_eq!(
+      csv.lines().collect::<Vec<_>>(),
+      [
+        "url,filename,status,bytes,elapsed_secs,http_status,error",
+        "https://example.com/a.bin,a.bin,downloaded,10,0.5,200,",
+        "\"https://example.com/b,c\",\"b,c\",failed,,1.0,,404 Not Found",
+      ]
+    );
+  }
+
+  #[test]
+  fn toml_and_json_reports_hold_the_same_summary() {
+    let summary = sample();
+    let json: serde_json::Value =
+      serde_json::from_str(&summary.render(ReportFormat::Json).unwrap())
+        .unwrap();
+    let toml: serde_json::Value = toml::from_str::<toml::Value>(
+      &summary.render(ReportFormat::Toml).unwrap(),
+    )
+    .map(|v| serde_json::to_value(v).unwrap())
+    .unwrap();
+    assert_eq!(json, toml);
+    assert_eq!(toml["results"][0]["response"]["headers"]["etag"], "\"abc\"");
+    assert_eq!(toml["throttled"]["example.com"], 3);
+    assert_eq!(toml["retries"]["timeout"], 2);
+  }
+
+  #[test]
+  fn schema_lists_timeout_retries() {
+    let schema = serde_json::to_value(RunSummary::json_schema()).unwrap();
+    let retries = &schema["definitions"]["RetryCounts"]["properties"];
+    assert!(retries.get("timeout").is_some());
+  }
+}
@@ -1,10 +1,16 @@
 use dotenvy::dotenv;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
-pub fn init_tracing() {
+/// Log to stderr, at trace level for this crate when `trace` is set
+pub fn init_tracing(trace: bool, quiet: bool) {
   use std::io::IsTerminal;
   dotenv().ok();
   let pkg_name = env!("CARGO_PKG_NAME");
+  let level = if trace {
+    "trace"
+  } else {
+    "debug"
+  };
   let format = tracing_subscriber::fmt::format()
     .with_level(true)
     .with_thread_names(true)
@@ -13,19 +19,325 @@ pub fn init_tracing() {
     .with_file(false)
     .with_line_number(false)
     .compact();
-  let stderr_subscriber = Registry::default()
-    .with(
-      EnvFilter::from_default_env()
-        .add_directive(tracing::Level::INFO.into())
-        .add_directive(format!("{}=debug", pkg_name).parse().unwrap())
-        .add_directive("multifiledownloader=debug".parse().unwrap()),
-    )
-    .with(
-      tracing_subscriber::fmt::layer()
-        .with_ansi(std::io::stderr().is_terminal())
-        .with_writer(std::io::stderr)
-        .event_format(format.clone()),
-    );
+  // Quiet runs only log errors, whatever RUST_LOG says
+  let filter = if quiet {
+    EnvFilter::new("error")
+  } else {
+    EnvFilter::from_default_env()
+      .add_directive(tracing::Level::INFO.into())
+      .add_directive(format!("{}={}", pkg_name, level).parse().unwrap())
+      .add_directive(format!("multifiledownloader={}", level).parse().unwrap())
+  };
+  let stderr_subscriber = Registry::default().with(filter).with(
+    tracing_subscriber::fmt::layer()
+      .with_ansi(std::io::stderr().is_terminal())
+      .with_writer(std::io::stderr)
+      .event_format(format.clone()),
+  );
 
   tracing::subscriber::set_global_default(stderr_subscriber).unwrap();
 }
+
+/// Parse an octal file mode such as `600`, `0640` or `0o600`
+pub fn parse_file_mode(s: &str) -> Result<u32, String> {
+  let digits = s.trim().trim_start_matches("0o");
+  u32::from_str_radix(digits, 8)
+    .ok()
+    .filter(|&mode| mode <= 0o7777)
+    .ok_or_else(|| format!("Invalid octal file mode: {}", s))
+}
+
+/// Parse a `Name: value` header. The value is left out of errors, it may be
+/// a secret.
+pub fn parse_header(s: &str) -> Result<(String, String), String> {
+  let (name, value) = s
+    .split_once(':')
+    .ok_or_else(|| "Invalid header, expected `Name: value`".to_string())?;
+  let name = name.trim();
+  if name.is_empty() {
+    return Err("Invalid header, the name is empty".to_string());
+  }
+  Ok((name.to_string(), value.trim().to_string()))
+}
+
+/// Whether a failed disk write may succeed if retried: a full disk, I/O
+/// errors and interruptions, as opposed to permanent errors like permission
+/// problems
+pub fn is_transient_io_error(e: &std::io::Error) -> bool {
+  use std::io::ErrorKind;
+  // EIO has no dedicated ErrorKind
+  const EIO: i32 = 5;
+  matches!(
+    e.kind(),
+    ErrorKind::StorageFull
+      | ErrorKind::ResourceBusy
+      | ErrorKind::Interrupted
+      | ErrorKind::TimedOut
+      | ErrorKind::WouldBlock
+  ) || (cfg!(unix) && e.raw_os_error() == Some(EIO))
+}
+
+/// Reorder `urls` to round-robin across their hosts, keeping the relative
+/// order of URLs on the same host and of hosts by first appearance
+pub fn interleave_hosts(urls: Vec<String>) -> Vec<String> {
+  let mut queues: Vec<(String, std::collections::VecDeque<String>)> =
+    Vec::new();
+  for url in urls {
+    let host = url::Url::parse(&url)
+      .map(|u| u.authority().to_string())
+      .unwrap_or_default();
+    match queues.iter_mut().find(|(h, _)| *h == host) {
+      Some((_, queue)) => queue.push_back(url),
+      None => queues.push((host, [url].into())),
+    }
+  }
+  let mut interleaved = Vec::new();
+  while !queues.is_empty() {
+    queues.retain_mut(|(_, queue)| {
+      interleaved.extend(queue.pop_front());
+      !queue.is_empty()
+    });
+  }
+  interleaved
+}
+
+/// Read the non-empty lines of the file at `path` (with `~` expanded) with
+/// their 1-based line numbers, trimmed and skipping `#` comments
+pub fn read_list(path: &str) -> std::io::Result<Vec<(usize, String)>> {
+  let contents = std::fs::read_to_string(shellexpand::tilde(path).as_ref())
+    .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+  Ok(
+    contents
+      .lines()
+      .map(str::trim)
+      .enumerate()
+      .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+      .map(|(i, line)| (i + 1, line.to_string()))
+      .collect(),
+  )
+}
+
+/// Split a URL list entry into the URL and the filename given after a `|`,
+/// if any
+pub fn split_output_name(entry: &str) -> (&str, Option<&str>) {
+  match entry.rsplit_once('|') {
+    Some((url, name)) if !name.trim().is_empty() => {
+      (url.trim(), Some(name.trim()))
+    },
+    Some((url, _)) => (url.trim(), None),
+    None => (entry, None),
+  }
+}
+
+/// Read lines from `input` on a separate thread as they arrive, skipping
+/// blank lines and `#` comments.
+///
+/// At most `capacity` lines are buffered, reading pauses until they are
+/// received.
+pub fn stream_lines<R: std::io::BufRead + Send + 'static>(
+  input: R,
+  capacity: usize,
+) -> tokio::sync::mpsc::Receiver<String> {
+  let (tx, rx) = tokio::sync::mpsc::channel(capacity.max(1));
+  std::thread::spawn(move || {
+    for line in input.lines() {
+      let line = match line {
+        Ok(line) => line,
+        Err(e) => {
+          tracing::error!("Failed to read streamed input: {}", e);
+          break;
+        },
+      };
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      if tx.blocking_send(line.to_string()).is_err() {
+        break;
+      }
+    }
+  });
+  rx
+}
+
+/// Parse a `Retry-After` header value, either a number of seconds or an
+/// HTTP-date. Dates in the past mean no delay.
+pub fn parse_retry_after(value: &str) -> Option<std::time::Duration> {
+  let value = value.trim();
+  if let Ok(secs) = value.parse::<u64>() {
+    return Some(std::time::Duration::from_secs(secs));
+  }
+  let date = httpdate::parse_http_date(value).ok()?;
+  Some(date.duration_since(std::time::SystemTime::now()).unwrap_or_default())
+}
+
+/// Parse `s` as a URL that can be downloaded: http(s) with a host
+pub fn validate_url(s: &str) -> Result<url::Url, String> {
+  let url = url::Url::parse(s).map_err(|e| e.to_string())?;
+  if !matches!(url.scheme(), "http" | "https") {
+    return Err(format!("unsupported scheme {}", url.scheme()));
+  }
+  if !url.has_host() {
+    return Err("missing host".to_string());
+  }
+  Ok(url)
+}
+
+/// On Windows, prefix paths close to `MAX_PATH` (260 characters) with `\\?\`
+/// so they, and the `.part.N` temporary files next to them, can still be
+/// opened. Other paths, and every path on other platforms, are returned as is.
+pub fn long_path(path: &std::path::Path) -> std::path::PathBuf {
+  #[cfg(windows)]
+  {
+    // Leave room for the temporary file suffixes
+    const LIMIT: usize = 260 - 16;
+    let raw = path.to_string_lossy();
+    if raw.len() >= LIMIT
+      && !raw.starts_with(r"\\?\")
+      && let Ok(absolute) = std::path::absolute(path)
+    {
+      let absolute = absolute.to_string_lossy();
+      return match absolute.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{}", unc).into(),
+        None => format!(r"\\?\{}", absolute).into(),
+      };
+    }
+  }
+  path.to_path_buf()
+}
+
+/// Move `from` to `to`, falling back to copy and delete when they are on
+/// different filesystems
+pub fn move_file(
+  from: &std::path::Path,
+  to: &std::path::Path,
+) -> std::io::Result<()> {
+  if std::fs::rename(from, to).is_ok() {
+    return Ok(());
+  }
+  std::fs::copy(from, to)?;
+  std::fs::remove_file(from)
+}
+
+/// Name of the per-run subdirectory used by `--timestamped-dir`, e.g.
+/// `2024-06-01T12-00-00.123`. Uses UTC so names sort and never collide across
+/// daylight saving changes.
+pub fn timestamp_dirname() -> String {
+  let format = time::macros::format_description!(
+    "[year]-[month]-[day]T[hour]-[minute]-[second].[subsecond digits:3]"
+  );
+  time::OffsetDateTime::now_utc()
+    .format(&format)
+    .unwrap_or_else(|_| "run".to_string())
+}
+
+/// Create a fresh timestamped run directory under `base` and return it.
+///
+/// Runs started within the same millisecond get a `-N` suffix instead of
+/// sharing a directory; creation is atomic, so concurrent runs cannot both
+/// claim the same name.
+pub fn create_timestamped_dir(
+  base: &std::path::Path,
+) -> std::io::Result<std::path::PathBuf> {
+  std::fs::create_dir_all(base)?;
+  let name = timestamp_dirname();
+  let mut dir = base.join(&name);
+  let mut n = 1;
+  loop {
+    match std::fs::create_dir(&dir) {
+      Ok(()) => return Ok(dir),
+      Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+        dir = base.join(format!("{}-{}", name, n));
+        n += 1;
+      },
+      Err(e) => return Err(e),
+    }
+  }
+}
+
+/// Parse a byte size with an optional decimal (`k`, `M`, `G`) or binary
+/// (`KiB`, `MiB`, `GiB`) suffix, e.g. `500k`, `1M`, `64KiB` or `4096`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+  let s = s.trim();
+  let split =
+    s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+  let (number, suffix) = s.split_at(split);
+  let number: f64 =
+    number.parse().map_err(|_| format!("Invalid size: {}", s))?;
+  let multiplier: u64 = match suffix.trim().to_lowercase().as_str() {
+    "" | "b" => 1,
+    "k" | "kb" => 1_000,
+    "m" | "mb" => 1_000_000,
+    "g" | "gb" => 1_000_000_000,
+    "kib" => 1 << 10,
+    "mib" => 1 << 20,
+    "gib" => 1 << 30,
+    other => return Err(format!("Unknown size suffix: {}", other)),
+  };
+  Ok((number * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, SystemTime};
+
+  use super::*;
+
+  #[test]
+  fn timestamped_dirs_never_collide() {
+    let base = tempfile::tempdir().unwrap();
+    let first = create_timestamped_dir(base.path()).unwrap();
+    let second = create_timestamped_dir(base.path()).unwrap();
+    assert_ne!(first, second);
+    assert!(first.is_dir() && second.is_dir());
+  }
+
+  #[cfg(windows)]
+  #[test]
+  fn long_paths_get_the_extended_length_prefix() {
+    let short = std::path::Path::new(r"C:\downloads\file.bin");
+    assert_eq!(long_path(short), short);
+
+    let long = format!(r"C:\downloads\{}\file.bin", "d".repeat(250));
+    let prefixed = long_path(std::path::Path::new(&long));
+    assert_eq!(prefixed.to_string_lossy(), format!(r"\\?\{}", long));
+    // Already prefixed paths are left alone
+    assert_eq!(long_path(&prefixed), prefixed);
+
+    let unc = format!(r"\\server\share\{}\file.bin", "d".repeat(250));
+    assert_eq!(
+      long_path(std::path::Path::new(&unc)).to_string_lossy(),
+      format!(r"\\?\UNC\server\share\{}\file.bin", "d".repeat(250))
+    );
+  }
+
+  #[cfg(not(windows))]
+  #[test]
+  fn long_paths_are_kept_elsewhere() {
+    let long = format!("/downloads/{}/file.bin", "d".repeat(300));
+    let path = std::path::Path::new(&long);
+    assert_eq!(long_path(path), path);
+  }
+
+  #[test]
+  fn retry_after_accepts_delta_seconds() {
+    assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+    assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+    assert_eq!(parse_retry_after("-5"), None);
+    assert_eq!(parse_retry_after("1.5"), None);
+    assert_eq!(parse_retry_after("soon"), None);
+  }
+
+  #[test]
+  fn retry_after_accepts_http_dates() {
+    let in_a_minute = SystemTime::now() + Duration::from_secs(60);
+    let delay = parse_retry_after(&httpdate::fmt_http_date(in_a_minute));
+    // HTTP-dates have whole second precision
+    assert!(delay.is_some_and(|d| (58..=60).contains(&d.as_secs())));
+    assert_eq!(
+      parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+      Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00"), None);
+  }
+}
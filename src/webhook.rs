@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::summary::RunSummary;
+
+/// Delivery attempts per event before giving up
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Event POSTed to the webhook as JSON
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+  /// A single download finished, successfully or not
+  File {
+    url:      String,
+    filename: String,
+    success:  bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error:    Option<String>,
+  },
+  /// The whole batch finished
+  Batch { summary: RunSummary },
+}
+
+/// Webhook notified when downloads and the batch complete
+#[derive(Clone)]
+pub struct Webhook {
+  /// Plain client, so headers given with `--header` never reach the webhook
+  client:      Client,
+  pub url:     url::Url,
+  /// Secret used to sign payloads with HMAC-SHA256 in the
+  /// `X-Signature-256` header
+  pub secret:  Option<String>,
+  /// Timeout of each delivery attempt
+  pub timeout: Duration,
+}
+
+impl std::fmt::Debug for Webhook {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Webhook")
+      .field("url", &self.url)
+      .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+      .field("timeout", &self.timeout)
+      .finish()
+  }
+}
+
+impl Webhook {
+  pub fn new(url: url::Url, secret: Option<String>, timeout: Duration) -> Self {
+    Webhook {
+      client: Client::new(),
+      url,
+      secret,
+      timeout,
+    }
+  }
+
+  /// POST `event` to the webhook, retrying failed deliveries with a backoff.
+  ///
+  /// Delivery failures are logged and never fail the download.
+  pub async fn send(&self, event: &WebhookEvent) {
+    let body = match serde_json::to_vec(event) {
+      Ok(body) => body,
+      Err(e) => {
+        tracing::warn!("Failed to serialize webhook event: {}", e);
+        return;
+      },
+    };
+    let signature = self.secret.as_ref().map(|secret| sign(secret, &body));
+    for attempt in 1..=MAX_ATTEMPTS {
+      let mut request = self
+        .client
+        .post(self.url.clone())
+        .timeout(self.timeout)
+        .header("content-type", "application/json")
+        .body(body.clone());
+      if let Some(signature) = &signature {
+        request = request.header("x-signature-256", signature);
+      }
+      match request.send().await.and_then(|r| r.error_for_status()) {
+        Ok(_) => return,
+        Err(e) => tracing::warn!(
+          "Webhook delivery to {} failed ({}/{}): {}",
+          self.url,
+          attempt,
+          MAX_ATTEMPTS,
+          e
+        ),
+      }
+      if attempt < MAX_ATTEMPTS {
+        tokio::time::sleep(Duration::from_millis(500 << attempt)).await;
+      }
+    }
+  }
+}
+
+/// `sha256=<hex HMAC-SHA256 of body>`
+fn sign(secret: &str, body: &[u8]) -> String {
+  let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+    .expect("HMAC accepts keys of any length");
+  mac.update(body);
+  let digest = mac.finalize().into_bytes();
+  let hex = digest.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+  format!("sha256={}", hex)
+}
@@ -0,0 +1,171 @@
+//! Scripted HTTP server for integration tests
+// Each test crate uses a different part of the helpers
+#![allow(dead_code)]
+
+use std::{
+  collections::HashMap,
+  net::SocketAddr,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use multifiledownloader::{client::ClientOptions, Downloader};
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream},
+};
+
+/// A request received by the [`MockServer`]
+#[derive(Debug, Clone)]
+pub struct Request {
+  pub method:  String,
+  pub path:    String,
+  /// Header names are lowercase
+  pub headers: HashMap<String, String>,
+}
+
+/// A response sent by the [`MockServer`]
+#[derive(Debug, Clone, Default)]
+pub struct Response {
+  pub status:  u16,
+  pub headers: Vec<(String, String)>,
+  pub body:    Vec<u8>,
+  /// Wait before answering
+  pub delay:   Option<Duration>,
+}
+
+impl Response {
+  pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+    Response {
+      status,
+      body: body.into(),
+      ..Default::default()
+    }
+  }
+
+  pub fn header(mut self, name: &str, value: impl ToString) -> Self {
+    self.headers.push((name.to_string(), value.to_string()));
+    self
+  }
+
+  pub fn delay(mut self, delay: Duration) -> Self {
+    self.delay = Some(delay);
+    self
+  }
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// HTTP/1.1 server answering every request with a handler, one request per
+/// connection
+pub struct MockServer {
+  addr:     SocketAddr,
+  requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+  pub async fn start(
+    handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+  ) -> Self {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let handler: Arc<Handler> = Arc::new(handler);
+    let log = requests.clone();
+    tokio::spawn(async move {
+      while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(serve(stream, handler.clone(), log.clone()));
+      }
+    });
+    MockServer {
+      addr,
+      requests,
+    }
+  }
+
+  /// URL of `path` on this server
+  pub fn url(&self, path: &str) -> String {
+    format!("http://{}{}", self.addr, path)
+  }
+
+  /// Requests received so far
+  pub fn requests(&self) -> Vec<Request> {
+    self.requests.lock().unwrap().clone()
+  }
+
+  /// GET requests received so far
+  pub fn gets(&self) -> Vec<Request> {
+    self.requests().into_iter().filter(|r| r.method == "GET").collect()
+  }
+}
+
+async fn serve(
+  mut stream: TcpStream,
+  handler: Arc<Handler>,
+  log: Arc<Mutex<Vec<Request>>>,
+) -> std::io::Result<()> {
+  let mut raw = Vec::new();
+  let mut buf = [0; 4096];
+  while !raw.windows(4).any(|w| w == b"\r\n\r\n") {
+    let n = stream.read(&mut buf).await?;
+    if n == 0 {
+      return Ok(());
+    }
+    raw.extend_from_slice(&buf[..n]);
+  }
+  let text = String::from_utf8_lossy(&raw);
+  let mut lines = text.lines();
+  let mut start = lines.next().unwrap_or_default().split(' ');
+  let request = Request {
+    method:  start.next().unwrap_or_default().to_string(),
+    path:    start.next().unwrap_or_default().to_string(),
+    headers: lines
+      .take_while(|line| !line.is_empty())
+      .filter_map(|line| line.split_once(':'))
+      .map(|(name, value)| (name.to_lowercase(), value.trim().to_string()))
+      .collect(),
+  };
+  log.lock().unwrap().push(request.clone());
+  let response = handler(&request);
+  if let Some(delay) = response.delay {
+    tokio::time::sleep(delay).await;
+  }
+  let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+  let mut has_length = false;
+  for (name, value) in &response.headers {
+    has_length |= name.eq_ignore_ascii_case("content-length");
+    head.push_str(&format!("{}: {}\r\n", name, value));
+  }
+  if !has_length {
+    head.push_str(&format!("content-length: {}\r\n", response.body.len()));
+  }
+  head.push_str("connection: close\r\n\r\n");
+  stream.write_all(head.as_bytes()).await?;
+  if request.method != "HEAD" {
+    stream.write_all(&response.body).await?;
+  }
+  stream.shutdown().await
+}
+
+/// Downloader for `urls` into `dest` with a single worker and no progress
+/// bars
+pub fn downloader(urls: Vec<String>, dest: &std::path::Path) -> Downloader {
+  downloader_with(urls, dest, ClientOptions::default())
+}
+
+/// Like [`downloader`], building the client from `client_opts`
+pub fn downloader_with(
+  urls: Vec<String>,
+  dest: &std::path::Path,
+  client_opts: ClientOptions,
+) -> Downloader {
+  Downloader::new(
+    urls,
+    dest.to_string_lossy().to_string(),
+    1,
+    false,
+    client_opts,
+  )
+  .unwrap()
+  .with_progress(false)
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use std::{
+  path::Path,
+  sync::{Arc, Mutex},
+};
+
+use common::{downloader, MockServer, Response};
+use multifiledownloader::{
+  error::DownloadError,
+  lock::ManifestLock,
+  Downloader,
+};
+
+fn locked(
+  urls: Vec<String>,
+  dest: &Path,
+  path: &Path,
+  frozen: bool,
+) -> Downloader {
+  downloader(urls, dest).with_manifest_lock(Some(ManifestLock {
+    path: path.to_path_buf(),
+    frozen,
+  }))
+}
+
+#[tokio::test]
+async fn frozen_run_detects_drift() {
+  let body = Arc::new(Mutex::new("v1".to_string()));
+  let served = body.clone();
+  let server = MockServer::start(move |_| {
+    Response::new(200, served.lock().unwrap().clone())
+  })
+  .await;
+  let dir = tempfile::tempdir().unwrap();
+  let lock = dir.path().join("mfd.lock");
+  let urls = vec![server.url("/a.txt")];
+
+  let dest = tempfile::tempdir().unwrap();
+  locked(urls.clone(), dest.path(), &lock, false).run().await.unwrap();
+  assert!(lock.exists());
+
+  let dest = tempfile::tempdir().unwrap();
+  locked(urls.clone(), dest.path(), &lock, true).run().await.unwrap();
+
+  *body.lock().unwrap() = "v2".to_string();
+  let dest = tempfile::tempdir().unwrap();
+  let err = locked(urls, dest.path(), &lock, true).run().await.unwrap_err();
+  match err {
+    DownloadError::LockMismatch(drift) => assert!(drift.contains("checksum")),
+    other => panic!("expected a lock mismatch, got {}", other),
+  }
+}
+
+#[tokio::test]
+async fn failed_run_keeps_previous_lock() {
+  let server = MockServer::start(|request| match request.path.as_str() {
+    "/a.txt" => Response::new(200, "a"),
+    _ => Response::new(404, ""),
+  })
+  .await;
+  let dir = tempfile::tempdir().unwrap();
+  let lock = dir.path().join("mfd.lock");
+  std::fs::write(&lock, "previous").unwrap();
+
+  let dest = tempfile::tempdir().unwrap();
+  let urls = vec![server.url("/a.txt"), server.url("/missing.txt")];
+  let summary = locked(urls, dest.path(), &lock, false).run().await.unwrap();
+  assert_eq!(summary.failed, 1);
+  assert_eq!(std::fs::read_to_string(&lock).unwrap(), "previous");
+}
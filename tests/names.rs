@@ -0,0 +1,38 @@
+mod common;
+
+use common::{downloader, MockServer, Response};
+
+/// Serve every path as `report.pdf`, with the path as the body
+fn same_disposition(request: &common::Request) -> Response {
+  Response::new(200, request.path.clone())
+    .header("content-disposition", r#"attachment; filename="report.pdf""#)
+}
+
+#[tokio::test]
+async fn same_disposition_names_do_not_overwrite_each_other() {
+  let server = MockServer::start(same_disposition).await;
+  let dest = tempfile::tempdir().unwrap();
+  let urls = vec![server.url("/a"), server.url("/b")];
+  let summary = downloader(urls, dest.path()).run().await.unwrap();
+  assert_eq!(summary.downloaded, 2);
+  let read = |name: &str| std::fs::read_to_string(dest.path().join(name));
+  assert_eq!(read("report.pdf").unwrap(), "/a");
+  assert_eq!(read("report_1.pdf").unwrap(), "/b");
+}
+
+#[tokio::test]
+async fn disposition_names_do_not_take_derived_names() {
+  let server = MockServer::start(|request| match request.path.as_str() {
+    "/report.pdf" => Response::new(200, "derived"),
+    _ => same_disposition(request),
+  })
+  .await;
+  let dest = tempfile::tempdir().unwrap();
+  // The second URL derives the name the first is served as
+  let urls = vec![server.url("/get"), server.url("/report.pdf")];
+  let summary = downloader(urls, dest.path()).run().await.unwrap();
+  assert_eq!(summary.downloaded, 2);
+  let read = |name: &str| std::fs::read_to_string(dest.path().join(name));
+  assert_eq!(read("report_1.pdf").unwrap(), "/get");
+  assert_eq!(read("report.pdf").unwrap(), "derived");
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use common::{downloader, MockServer, Response};
+use sha2::Digest;
+
+#[tokio::test]
+async fn sidecar_redacts_sensitive_headers() {
+  let server = MockServer::start(|_| {
+    Response::new(200, "content")
+      .header("set-cookie", "session=secret")
+      .header("set-cookie", "other=secret")
+      .header("vary", "accept")
+      .header("vary", "origin")
+  })
+  .await;
+  let dest = tempfile::tempdir().unwrap();
+  let urls = vec![server.url("/file.txt")];
+  let summary =
+    downloader(urls, dest.path()).with_provenance(true).run().await.unwrap();
+  assert_eq!(summary.downloaded, 1);
+  let sidecar = dest.path().join("file.txt.meta.json");
+  let json = std::fs::read_to_string(sidecar).unwrap();
+  assert!(!json.contains("secret"));
+  let meta: serde_json::Value = serde_json::from_str(&json).unwrap();
+  assert_eq!(meta["headers"]["set-cookie"], "[redacted]");
+  assert_eq!(meta["headers"]["vary"], "accept, origin");
+}
+
+#[tokio::test]
+async fn sidecar_records_streamed_checksum() {
+  let server = MockServer::start(|_| Response::new(200, "content")).await;
+  let dest = tempfile::tempdir().unwrap();
+  let url = server.url("/file.txt");
+  // An expected checksum makes the download stream its SHA-256
+  let expected = format!("{:x}", sha2::Sha256::digest(b"content"));
+  let checksums = [(url.clone(), expected.clone())].into_iter().collect();
+  downloader(vec![url], dest.path())
+    .with_expected_checksums(checksums)
+    .with_provenance(true)
+    .run()
+    .await
+    .unwrap();
+  let sidecar = dest.path().join("file.txt.meta.json");
+  let json = std::fs::read_to_string(sidecar).unwrap();
+  let meta: serde_json::Value = serde_json::from_str(&json).unwrap();
+  assert_eq!(meta["sha256"], expected);
+}
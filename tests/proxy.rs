@@ -0,0 +1,48 @@
+mod common;
+
+use common::{downloader_with, MockServer, Response};
+use multifiledownloader::client::ClientOptions;
+
+/// Answer every request, proxied or not, with a small body
+fn serve(_: &common::Request) -> Response {
+  Response::new(200, "through the proxy")
+}
+
+fn via(proxy: String) -> ClientOptions {
+  ClientOptions {
+    proxy: Some(proxy),
+    ..Default::default()
+  }
+}
+
+#[tokio::test]
+async fn requests_go_through_the_proxy() {
+  let proxy = MockServer::start(serve).await;
+  let dest = tempfile::tempdir().unwrap();
+  let url = "http://files.invalid/a.txt".to_string();
+  let summary =
+    downloader_with(vec![url.clone()], dest.path(), via(proxy.url("")))
+      .run()
+      .await
+      .unwrap();
+  assert_eq!(summary.downloaded, 1);
+  // Proxies get the absolute URL in the request line
+  assert_eq!(proxy.gets()[0].path, url);
+  assert_eq!(
+    std::fs::read_to_string(dest.path().join("a.txt")).unwrap(),
+    "through the proxy"
+  );
+}
+
+#[tokio::test]
+async fn proxy_credentials_are_sent() {
+  let proxy = MockServer::start(serve).await;
+  let dest = tempfile::tempdir().unwrap();
+  let proxy_url = proxy.url("").replace("http://", "http://user:pass@");
+  let urls = vec!["http://files.invalid/b.txt".to_string()];
+  downloader_with(urls, dest.path(), via(proxy_url)).run().await.unwrap();
+  assert_eq!(
+    proxy.gets()[0].headers["proxy-authorization"],
+    "Basic dXNlcjpwYXNz"
+  );
+}
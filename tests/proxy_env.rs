@@ -0,0 +1,40 @@
+//! Environment proxy settings, in a binary of their own as they change the
+//! process environment
+
+mod common;
+
+use common::{downloader_with, MockServer, Response};
+use multifiledownloader::client::ClientOptions;
+
+#[tokio::test]
+async fn no_proxy_hosts_bypass_the_proxy_and_env_proxies_are_replaced() {
+  // SAFETY: this binary runs no other test that could read the environment
+  // concurrently
+  unsafe {
+    std::env::set_var("NO_PROXY", "localhost");
+    std::env::set_var("HTTP_PROXY", "http://127.0.0.1:1");
+  }
+  let proxy = MockServer::start(|_| Response::new(200, "proxied")).await;
+  let origin = MockServer::start(|_| Response::new(200, "direct")).await;
+  let dest = tempfile::tempdir().unwrap();
+  let port = origin.url("").rsplit(':').next().unwrap().to_string();
+  let urls = vec![
+    format!("http://localhost:{}/direct.txt", port),
+    "http://files.invalid/proxied.txt".to_string(),
+  ];
+  let summary = downloader_with(
+    urls,
+    dest.path(),
+    ClientOptions {
+      proxy: Some(proxy.url("")),
+      ..Default::default()
+    },
+  )
+  .run()
+  .await
+  .unwrap();
+  assert_eq!(summary.downloaded, 2);
+  assert_eq!(origin.gets().len(), 1);
+  assert_eq!(proxy.gets().len(), 1);
+  assert_eq!(proxy.gets()[0].path, "http://files.invalid/proxied.txt");
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{downloader, MockServer, Response};
+use multifiledownloader::report::REDACTED;
+
+fn handler(request: &common::Request) -> Response {
+  match request.path.as_str() {
+    "/missing" => Response::new(404, "not found"),
+    _ => Response::new(200, "hello")
+      .header("x-served-by", "mock")
+      .header("set-cookie", "session=secret"),
+  }
+}
+
+#[tokio::test]
+async fn report_includes_last_response_when_enabled() {
+  let server = MockServer::start(handler).await;
+  let dest = tempfile::tempdir().unwrap();
+  let urls = vec![server.url("/file.txt"), server.url("/missing")];
+  let summary = downloader(urls, dest.path())
+    .with_include_headers_in_report(true)
+    .run()
+    .await
+    .unwrap();
+
+  let ok = summary.results.iter().find(|r| r.filename == "file.txt").unwrap();
+  let response = ok.response.as_ref().unwrap();
+  assert_eq!(response.status, 200);
+  assert_eq!(response.headers["x-served-by"], "mock");
+  assert_eq!(response.headers["set-cookie"], REDACTED);
+
+  let missing = summary.results.iter().find(|r| r.filename == "missing");
+  assert_eq!(missing.unwrap().response.as_ref().unwrap().status, 404);
+}
+
+#[tokio::test]
+async fn report_omits_responses_by_default() {
+  let server = MockServer::start(handler).await;
+  let dest = tempfile::tempdir().unwrap();
+  let summary =
+    downloader(vec![server.url("/file.txt")], dest.path()).run().await.unwrap();
+  assert!(summary.results[0].response.is_none());
+  let json = serde_json::to_value(&summary).unwrap();
+  assert!(json["results"][0].get("response").is_none());
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::{downloader, MockServer, Response};
+use multifiledownloader::parse_content_range_total;
+use sha2::{Digest, Sha256};
+
+/// Body served by the test servers
+fn body() -> Vec<u8> {
+  (0..100u8).collect()
+}
+
+/// Answer ranged GETs with 206 and `Content-Range`, and HEADs without any
+/// size so the download has to learn it from the GET
+fn ranged_without_head_size(request: &common::Request) -> Response {
+  let body = body();
+  if request.method == "HEAD" {
+    return Response::new(200, "");
+  }
+  let start = request
+    .headers
+    .get("range")
+    .and_then(|r| r.strip_prefix("bytes="))
+    .and_then(|r| r.trim_end_matches('-').parse::<usize>().ok())
+    .unwrap_or(0);
+  Response::new(206, body[start..].to_vec()).header(
+    "content-range",
+    format!("bytes {}-{}/{}", start, body.len() - 1, body.len()),
+  )
+}
+
+#[test]
+fn content_range_total_is_parsed() {
+  assert_eq!(parse_content_range_total("bytes 0-99/100"), Some(100));
+  assert_eq!(parse_content_range_total("bytes 10-99/100"), Some(100));
+  assert_eq!(parse_content_range_total("bytes 0-99/*"), None);
+  assert_eq!(parse_content_range_total("bytes */100"), Some(100));
+  assert_eq!(parse_content_range_total("garbage"), None);
+}
+
+#[tokio::test]
+async fn size_comes_from_content_range_when_head_has_none() {
+  let server = MockServer::start(ranged_without_head_size).await;
+  let dest = tempfile::tempdir().unwrap();
+  let summary =
+    downloader(vec![server.url("/file.bin")], dest.path()).run().await.unwrap();
+  assert_eq!(summary.downloaded, 1);
+  assert_eq!(summary.results[0].bytes, Some(100));
+  assert_eq!(std::fs::read(dest.path().join("file.bin")).unwrap(), body());
+}
+
+#[tokio::test]
+async fn resume_completes_from_content_range_total() {
+  let server = MockServer::start(ranged_without_head_size).await;
+  let dest = tempfile::tempdir().unwrap();
+  std::fs::write(dest.path().join("file.bin.part"), &body()[..10]).unwrap();
+  let summary =
+    downloader(vec![server.url("/file.bin")], dest.path()).run().await.unwrap();
+  assert_eq!(summary.resumed, 1);
+  assert_eq!(server.gets()[0].headers["range"], "bytes=10-");
+  assert_eq!(std::fs::read(dest.path().join("file.bin")).unwrap(), body());
+}
+
+#[tokio::test]
+async fn resumed_requests_disable_compression() {
+  let server = MockServer::start(ranged_without_head_size).await;
+  let dest = tempfile::tempdir().unwrap();
+  std::fs::write(dest.path().join("file.bin.part"), &body()[..10]).unwrap();
+  downloader(vec![server.url("/file.bin")], dest.path()).run().await.unwrap();
+  let gets = server.gets();
+  assert_eq!(gets.len(), 1);
+  assert_eq!(gets[0].headers["accept-encoding"], "identity");
+}
+
+#[tokio::test]
+async fn fresh_requests_leave_compression_to_the_client() {
+  let server = MockServer::start(ranged_without_head_size).await;
+  let dest = tempfile::tempdir().unwrap();
+  downloader(vec![server.url("/file.bin")], dest.path()).run().await.unwrap();
+  let gets = server.gets();
+  assert_eq!(gets.len(), 1);
+  assert_ne!(
+    gets[0].headers.get("accept-encoding").map(String::as_str),
+    Some("identity")
+  );
+}
+
+fn sha256(bytes: &[u8]) -> String {
+  Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[tokio::test]
+async fn resumed_download_digest_covers_the_partial() {
+  let server = MockServer::start(ranged_without_head_size).await;
+  let dest = tempfile::tempdir().unwrap();
+  std::fs::write(dest.path().join("file.bin.part"), &body()[..10]).unwrap();
+  let expected = HashMap::from([("file.bin".to_string(), sha256(&body()))]);
+  let summary = downloader(vec![server.url("/file.bin")], dest.path())
+    .with_expected_checksums(expected)
+    .run()
+    .await
+    .unwrap();
+  assert_eq!(summary.resumed, 1);
+}
+
+#[tokio::test]
+async fn checksum_mismatch_removes_the_download() {
+  let server = MockServer::start(ranged_without_head_size).await;
+  let dest = tempfile::tempdir().unwrap();
+  let expected = HashMap::from([("file.bin".to_string(), sha256(b"other"))]);
+  let summary = downloader(vec![server.url("/file.bin")], dest.path())
+    .with_expected_checksums(expected)
+    .run()
+    .await
+    .unwrap();
+  assert_eq!(summary.failed, 1);
+  assert!(summary.results[0].error.as_ref().unwrap().contains("hecksum"));
+  assert!(!dest.path().join("file.bin").exists());
+  assert!(!dest.path().join("file.bin.part").exists());
+}
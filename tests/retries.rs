@@ -0,0 +1,167 @@
+mod common;
+
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use common::{downloader_with, MockServer, Response};
+use multifiledownloader::{client::ClientOptions, Downloader};
+use sha2::{Digest, Sha256};
+
+const BODY: &str = "retried body";
+
+/// How the scripted server answers one GET
+#[derive(Clone, Copy)]
+enum Step {
+  /// Fail with this status
+  Fail(u16),
+  /// Answer too late for the client timeout
+  Hang,
+  /// Serve a body of the right length with the wrong content
+  Corrupt,
+  /// Answer 429 with this `Retry-After`
+  Throttle(&'static str),
+}
+
+/// Answer HEADs at once and the GETs with `script`, in order, serving the
+/// body once it runs out
+async fn scripted(script: Vec<Step>) -> MockServer {
+  let gets = Arc::new(AtomicUsize::new(0));
+  MockServer::start(move |request| {
+    if request.method == "HEAD" {
+      return Response::new(200, BODY);
+    }
+    match script.get(gets.fetch_add(1, Ordering::SeqCst)) {
+      Some(Step::Fail(status)) => Response::new(*status, "failure"),
+      Some(Step::Hang) => {
+        Response::new(200, BODY).delay(Duration::from_secs(2))
+      },
+      Some(Step::Corrupt) => Response::new(200, "corrupt body"),
+      Some(Step::Throttle(retry_after)) => {
+        Response::new(429, "slow down").header("retry-after", retry_after)
+      },
+      None => Response::new(200, BODY),
+    }
+  })
+  .await
+}
+
+/// Downloader timing out requests after half a second, retrying other
+/// failures `retries` times with a short backoff and verifying the body
+fn retrying(
+  server: &MockServer,
+  dest: &std::path::Path,
+  retries: u32,
+) -> Downloader {
+  let client_opts = ClientOptions {
+    timeout: Some(Duration::from_millis(500)),
+    ..Default::default()
+  };
+  let digest = Sha256::digest(BODY)
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect::<String>();
+  downloader_with(vec![server.url("/file.txt")], dest, client_opts)
+    .with_retries(retries, Duration::from_millis(10))
+    .with_expected_checksums(HashMap::from([("file.txt".to_string(), digest)]))
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried() {
+  let server = scripted(vec![Step::Fail(503), Step::Fail(500)]).await;
+  let dest = tempfile::tempdir().unwrap();
+  let summary = retrying(&server, dest.path(), 2).run().await.unwrap();
+  assert_eq!(summary.downloaded, 1);
+  assert_eq!(summary.retries.server_error, 2);
+  assert_eq!(server.gets().len(), 3);
+  assert_eq!(
+    std::fs::read_to_string(dest.path().join("file.txt")).unwrap(),
+    BODY
+  );
+}
+
+#[tokio::test]
+async fn exhausted_retries_fail_the_download() {
+  let server = scripted(vec![Step::Fail(503); 3]).await;
+  let dest = tempfile::tempdir().unwrap();
+  let summary = retrying(&server, dest.path(), 2).run().await.unwrap();
+  assert_eq!(summary.failed, 1);
+  assert_eq!(summary.retries.server_error, 2);
+  assert_eq!(server.gets().len(), 3);
+  assert!(!dest.path().join("file.txt").exists());
+}
+
+#[tokio::test]
+async fn client_errors_are_not_retried() {
+  let server = scripted(vec![Step::Fail(404)]).await;
+  let dest = tempfile::tempdir().unwrap();
+  let summary = retrying(&server, dest.path(), 2).run().await.unwrap();
+  assert_eq!(summary.failed, 1);
+  assert_eq!(server.gets().len(), 1);
+}
+
+#[tokio::test]
+async fn timeouts_do_not_use_up_other_retries() {
+  let server = scripted(vec![Step::Fail(503), Step::Hang, Step::Hang]).await;
+  let dest = tempfile::tempdir().unwrap();
+  let summary = retrying(&server, dest.path(), 1)
+    .with_timeout_retries(2)
+    .run()
+    .await
+    .unwrap();
+  assert_eq!(summary.downloaded, 1);
+  assert_eq!(server.gets().len(), 4);
+  assert_eq!(summary.retries.timeout, 2);
+  assert_eq!(summary.retries.server_error, 1);
+  assert_eq!(summary.retries.network, 0);
+}
+
+#[tokio::test]
+async fn checksum_mismatches_have_their_own_budget() {
+  let script = vec![Step::Corrupt, Step::Fail(503), Step::Corrupt];
+  let server = scripted(script).await;
+  let dest = tempfile::tempdir().unwrap();
+  let summary = retrying(&server, dest.path(), 1)
+    .with_checksum_retries(2)
+    .run()
+    .await
+    .unwrap();
+  assert_eq!(summary.downloaded, 1);
+  assert_eq!(summary.retries.checksum, 2);
+  assert_eq!(summary.retries.server_error, 1);
+  assert_eq!(
+    std::fs::read_to_string(dest.path().join("file.txt")).unwrap(),
+    BODY
+  );
+}
+
+#[tokio::test]
+async fn checksum_budget_runs_out() {
+  let server = scripted(vec![Step::Corrupt; 2]).await;
+  let dest = tempfile::tempdir().unwrap();
+  let summary = retrying(&server, dest.path(), 5)
+    .with_checksum_retries(1)
+    .run()
+    .await
+    .unwrap();
+  assert_eq!(summary.failed, 1);
+  assert_eq!(server.gets().len(), 2);
+  assert!(!dest.path().join("file.txt").exists());
+}
+
+#[tokio::test]
+async fn retry_after_delays_the_next_request() {
+  let server = scripted(vec![Step::Throttle("1")]).await;
+  let dest = tempfile::tempdir().unwrap();
+  let start = std::time::Instant::now();
+  let summary = retrying(&server, dest.path(), 0).run().await.unwrap();
+  assert_eq!(summary.downloaded, 1);
+  assert_eq!(summary.throttled.values().sum::<usize>(), 1);
+  assert_eq!(server.gets().len(), 2);
+  assert!(start.elapsed() >= Duration::from_secs(1));
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use common::{downloader, MockServer, Response};
+use multifiledownloader::client::Auth;
+
+#[tokio::test]
+async fn presize_sends_credentials() {
+  let server = MockServer::start(|request| {
+    match request.headers.get("authorization").map(String::as_str) {
+      Some("Bearer secret") => {
+        Response::new(200, "").header("content-length", 42)
+      },
+      _ => Response::new(401, ""),
+    }
+  })
+  .await;
+  let dest = tempfile::tempdir().unwrap();
+  let url = server.url("/file.bin");
+  let sizes = downloader(vec![url.clone()], dest.path())
+    .with_auth(Some(Auth::Bearer("secret".into())))
+    .presize()
+    .await;
+  assert_eq!(sizes, vec![(url, Some(42))]);
+  assert!(server.requests().iter().all(|r| r.method == "HEAD"));
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::{downloader, MockServer, Response};
+use sha2::{Digest, Sha256};
+
+const MIB: usize = 1 << 20;
+
+/// Serve `/<size>` as `size` bytes, honoring `bytes=start-end` ranges
+fn ranged(request: &common::Request) -> Response {
+  let size: usize = request.path.trim_start_matches('/').parse().unwrap();
+  let body = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+  let range = request
+    .headers
+    .get("range")
+    .and_then(|r| r.strip_prefix("bytes="))
+    .and_then(|r| r.split_once('-'))
+    .map(|(start, end)| {
+      let start: usize = start.parse().unwrap();
+      let end = end.parse().unwrap_or(size - 1).min(size - 1);
+      (start, end)
+    });
+  match range {
+    Some((start, end)) if request.method == "GET" => {
+      Response::new(206, body[start..=end].to_vec())
+        .header("content-range", format!("bytes {}-{}/{}", start, end, size))
+    },
+    _ => Response::new(200, body).header("accept-ranges", "bytes"),
+  }
+}
+
+async fn ranged_gets(size: usize, split: usize) -> usize {
+  let server = MockServer::start(ranged).await;
+  let dest = tempfile::tempdir().unwrap();
+  let summary =
+    downloader(vec![server.url(&format!("/{}", size))], dest.path())
+      .with_split(split)
+      .with_split_threshold(2 * MIB as u64)
+      .with_min_chunk_size(MIB as u64)
+      .run()
+      .await
+      .unwrap();
+  assert_eq!(summary.downloaded, 1);
+  let file = std::fs::read(dest.path().join(size.to_string())).unwrap();
+  assert!(file.iter().enumerate().all(|(i, &b)| b == i as u8));
+  server.gets().len()
+}
+
+#[tokio::test]
+async fn files_below_the_threshold_use_one_connection() {
+  assert_eq!(ranged_gets(MIB, 8).await, 1);
+}
+
+#[tokio::test]
+async fn ranges_are_no_smaller_than_the_minimum_chunk() {
+  assert_eq!(ranged_gets(3 * MIB + 10, 8).await, 3);
+  assert_eq!(ranged_gets(3 * MIB, 2).await, 2);
+}
+
+#[tokio::test]
+async fn split_download_digest_covers_all_segments() {
+  let size = 3 * MIB;
+  let body = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+  let digest = Sha256::digest(&body)
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect::<String>();
+  let server = MockServer::start(ranged).await;
+  let dest = tempfile::tempdir().unwrap();
+  let summary =
+    downloader(vec![server.url(&format!("/{}", size))], dest.path())
+      .with_split(3)
+      .with_split_threshold(0)
+      .with_expected_checksums(HashMap::from([(size.to_string(), digest)]))
+      .run()
+      .await
+      .unwrap();
+  assert_eq!(summary.downloaded, 1);
+  assert_eq!(server.gets().len(), 3);
+}